@@ -0,0 +1,276 @@
+//! Command-line tool that previews what wrapping functions does to a Rust source file, without needing a procedural macro crate.
+//! Only built with the `preview` feature.
+//!
+//! ```text
+//! cargo run --features preview --bin function-wrapper-preview -- src/handlers.rs --pre 'println!("start");' --select 'pub, name = "handle_*"' --diff
+//! ```
+//!
+//! The code and selectors can also be put in a config file given with `--config`, which is a list of `key = value;` entries:
+//!
+//! ```text
+//! select = (pub, name = "handle_*", skip = no_wrap);
+//! pre = { println!("start"); };
+//! post = { println!("end"); };
+//! ```
+//!
+//! Options given on the command line replace the ones in the config file.
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+use proc_macro2::{TokenStream, TokenTree, Delimiter};
+use syn::{Ident, Token};
+use syn::parse::{Parse, ParseStream};
+use function_wrapper::{FnFilter, WrappedFile};
+
+/// Help text that gets printed for `--help` and after errors in the arguments.
+const USAGE: &str = "\
+Usage: function-wrapper-preview <FILE> [OPTIONS]
+
+Prints a source file with code added to its functions, pretty-printed.
+
+Options:
+  --pre <CODE>           Code to run before each selected function
+  --post <CODE>          Code to run after each selected function
+  --select <SELECTORS>   Which functions to wrap, like 'pub, name = \"handle_*\", returns = Result' (all of them by default)
+  --config <FILE>        File with `select = (...);`, `pre = { ... };`, and `post = { ... };` entries
+  --diff                 Print a unified diff against the original file instead
+  -h, --help             Print this help text";
+
+/// What to add to which functions, from the command line and / or a config file.
+#[derive(Debug, Default)]
+struct Options
+{
+	/// The source file being previewed.
+	path: Option<PathBuf>,
+	/// Which functions get wrapped.
+	filter: Option<FnFilter>,
+	/// Code that runs before each selected function.
+	pre_code: Option<TokenStream>,
+	/// Code that runs after each selected function.
+	post_code: Option<TokenStream>,
+	/// Whether to print a unified diff instead of the whole woven file.
+	diff: bool
+}
+
+impl Options
+{
+	/// Replaces these options with any that are set in other options.
+	fn merge(&mut self, other: Options)
+	{
+		self.path = other.path.or(self.path.take());
+		self.filter = other.filter.or(self.filter.take());
+		self.pre_code = other.pre_code.or(self.pre_code.take());
+		self.post_code = other.post_code.or(self.post_code.take());
+		self.diff |= other.diff;
+	}
+}
+
+/// Parses the contents of a config file.
+impl Parse for Options
+{
+	/// Parses `key = value;` entries, where the keys are `select`, `pre`, and `post`.
+	fn parse(input: ParseStream) -> syn::Result<Self>
+	{
+		let mut options = Options::default();
+		while !input.is_empty()
+		{
+			let key: Ident = input.parse()?;
+			input.parse::<Token![=]>()?;
+			// Values are always delimited so that the code inside of them can have any tokens
+			let value = match input.parse::<TokenTree>()?
+			{
+				TokenTree::Group(group) if group.delimiter() != Delimiter::None => group,
+				other => return Err(syn::Error::new(other.span(), "expected a value in `(...)` or `{...}`"))
+			};
+			input.parse::<Token![;]>()?;
+			match key.to_string().as_str()
+			{
+				"select" => options.filter = Some(syn::parse2(value.stream())?),
+				"pre" => options.pre_code = Some(value.stream()),
+				"post" => options.post_code = Some(value.stream()),
+				_ => return Err(syn::Error::new(key.span(), "expected `select`, `pre`, or `post`"))
+			}
+		}
+		Ok(options)
+	}
+}
+
+fn main() -> ExitCode
+{
+	match run(std::env::args().skip(1))
+	{
+		Ok(output) =>
+		{
+			print!("{}", output);
+			ExitCode::SUCCESS
+		},
+		Err(error) =>
+		{
+			eprintln!("error: {}", error);
+			ExitCode::FAILURE
+		}
+	}
+}
+
+/// Previews what wrapping functions does to a source file.
+///
+/// Inputs:
+///
+/// `args`: The command-line arguments, not including the name of the program.
+///
+/// Outputs: The text to print, or an error message.
+fn run(args: impl Iterator<Item = String>) -> Result<String, String>
+{
+	let options = match parse_args(args)?
+	{
+		Some(options) => options,
+		None => return Ok(format!("{}\n", USAGE))
+	};
+	let path = options.path.ok_or_else(|| format!("no source file given\n\n{}", USAGE))?;
+	let mut file = WrappedFile::read(&path).map_err(|error| format!("{}: {}", path.display(), error))?;
+	file.filter = options.filter.unwrap_or_default();
+	// Make sure that the code parses before adding it to any functions
+	for function in file.get_functions_mut()
+	{
+		if let Some(pre_code) = &options.pre_code
+		{
+			function.try_set_pre_code(pre_code.clone()).map_err(|error| format!("pre code: {}", error))?;
+		}
+		if let Some(post_code) = &options.post_code
+		{
+			function.try_set_post_code(post_code.clone()).map_err(|error| format!("post code: {}", error))?;
+		}
+	}
+	// Report code that can't be added to a function instead of printing it as a `compile_error!`
+	let mut errors = Vec::new();
+	for function in file.get_functions()
+	{
+		if let Err(error) = function.try_to_item_fn()
+		{
+			let name = &function.function.sig.ident;
+			errors.extend(error.into_iter().map(|error| format!("`{}`: {}", name, error)));
+		}
+	}
+	if !errors.is_empty()
+	{
+		return Err(errors.join("\nerror: "));
+	}
+	let woven = prettyplease::unparse(&syn::File::from(&file));
+	if !options.diff
+	{
+		return Ok(woven);
+	}
+	// Pretty-print the original file too so that the diff only shows what wrapping changed
+	let original = prettyplease::unparse(&file.file);
+	let path = path.display().to_string();
+	let diff = similar::TextDiff::from_lines(&original, &woven);
+	Ok(diff.unified_diff().header(&path, &format!("{} (woven)", path)).to_string())
+}
+
+/// Parses the command-line arguments, reading the config file if one is given.
+///
+/// Inputs:
+///
+/// `args`: The command-line arguments, not including the name of the program.
+///
+/// Outputs: The options, `None` if the help text should be printed, or an error message.
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Options>, String>
+{
+	let mut config = Options::default();
+	let mut options = Options::default();
+	while let Some(arg) = args.next()
+	{
+		let mut value = |name: &str| args.next().ok_or_else(|| format!("`{}` needs a value\n\n{}", name, USAGE));
+		match arg.as_str()
+		{
+			"-h" | "--help" => return Ok(None),
+			"--diff" => options.diff = true,
+			"--pre" => options.pre_code = Some(parse_tokens(&value("--pre")?, "pre code")?),
+			"--post" => options.post_code = Some(parse_tokens(&value("--post")?, "post code")?),
+			"--select" => options.filter = Some(syn::parse_str(&value("--select")?).map_err(|error| format!("selectors: {}", error))?),
+			"--config" =>
+			{
+				let config_path = value("--config")?;
+				let source = std::fs::read_to_string(&config_path).map_err(|error| format!("{}: {}", config_path, error))?;
+				config = syn::parse_str(&source).map_err(|error| format!("{}: {}", config_path, error))?;
+			},
+			_ if arg.starts_with('-') => return Err(format!("unknown option `{}`\n\n{}", arg, USAGE)),
+			_ if options.path.is_none() => options.path = Some(PathBuf::from(arg)),
+			_ => return Err(format!("more than one source file given\n\n{}", USAGE))
+		}
+	}
+	// Options on the command line replace the ones in the config file
+	config.merge(options);
+	Ok(Some(config))
+}
+
+/// Parses code given on the command line into tokens.
+///
+/// Inputs:
+///
+/// `code`: The code.
+///
+/// `name`: What the code is for, to put in the error message.
+fn parse_tokens(code: &str, name: &str) -> Result<TokenStream, String>
+{
+	code.parse().map_err(|error| format!("{}: {}", name, error))
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	fn args(args: &[&str]) -> impl Iterator<Item = String>
+	{
+		args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>().into_iter()
+	}
+
+	#[test]
+	fn previews_woven_files()
+	{
+		let dir = std::env::temp_dir().join(format!("function-wrapper-preview-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let source = dir.join("handlers.rs");
+		std::fs::write(&source, "pub fn handle_get() -> u8 { 1 }\nfn helper() -> u8 { 2 }\n").unwrap();
+		let config = dir.join("preview.cfg");
+		std::fs::write(&config, "select = (pub); pre = { trace(); };").unwrap();
+		let source = source.display().to_string();
+		let config = config.display().to_string();
+		let woven = run(args(&[&source, "--config", &config])).unwrap();
+		let diff = run(args(&[&source, "--config", &config, "--post", "done();", "--diff"])).unwrap();
+		std::fs::remove_dir_all(&dir).unwrap();
+		assert_eq!(woven, "pub fn handle_get() -> u8 {\n    trace();\n    1\n}\nfn helper() -> u8 {\n    2\n}\n");
+		// The command line adds post code to the config file's pre code
+		assert!(diff.contains("+    trace();\n"));
+		assert!(diff.contains("+    done();\n"));
+		assert!(!diff.contains("+fn helper"));
+	}
+
+	#[test]
+	fn reports_bad_arguments()
+	{
+		assert!(run(args(&["--help"])).unwrap().starts_with("Usage:"));
+		assert!(run(args(&[])).unwrap_err().starts_with("no source file given"));
+		assert!(run(args(&["a.rs", "--bogus"])).unwrap_err().starts_with("unknown option `--bogus`"));
+		assert!(run(args(&["a.rs", "--pre"])).unwrap_err().starts_with("`--pre` needs a value"));
+		assert!(run(args(&["a.rs", "--select", "colour = red"])).unwrap_err().starts_with("selectors: expected `pub`"));
+		let error = syn::parse_str::<Options>("pre = trace();").unwrap_err();
+		assert_eq!(error.to_string(), "expected a value in `(...)` or `{...}`");
+	}
+
+	#[test]
+	fn reports_code_that_can_not_be_added()
+	{
+		let dir = std::env::temp_dir().join(format!("function-wrapper-preview-invalid-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let source = dir.join("handlers.rs");
+		std::fs::write(&source, "pub fn handle_get() -> u8 { 1 }\npub fn stop() -> ! { loop {} }\n").unwrap();
+		let source = source.display().to_string();
+		let error = run(args(&[&source, "--post", "done();"])).unwrap_err();
+		std::fs::remove_dir_all(&dir).unwrap();
+		// Only the function that the code can't be added to should be reported
+		assert!(error.starts_with("`stop`: post code"));
+		assert!(!error.contains("handle_get"));
+	}
+}
@@ -0,0 +1,585 @@
+//! Filters that decide which functions in a group of items get wrapped.
+//!
+//! A macro that wraps every function in an impl block (see `WrappedImpl`) or a module (see `WrappedMod`) can use a `FnFilter` to only wrap some of them, such as only the public ones or only the ones whose names match a pattern.
+//!
+//! ```rust,ignore
+//! let mut filter = FnFilter::default();
+//! // Only wrap public methods whose names start with `get_`.
+//! filter.set_pub_only(true);
+//! filter.add_name_pattern("get_*");
+//! // Let methods opt out of being wrapped with `#[no_trace]`.
+//! filter.set_skip_attr("no_trace");
+//! ```
+//!
+//! Filters can also be parsed from the arguments of an attribute, which is a comma-separated list of these selectors:
+//!
+//! - `pub`: Only public functions.
+//! - `async` or `!async`: Only `async fn`s, or only functions that aren't async.
+//! - `name = "pattern"`: Only functions whose names match the glob pattern, where `*` matches any number of characters and `?` matches one. Can be given more than once to match any of the patterns.
+//! - `receiver = &self`, `&mut self`, `self`, `_`, or `none`: Only methods that take `self` that way, methods that take `self` in any way, or functions that don't take `self`.
+//! - `attr = path`: Only functions with an attribute with this path. Can be given more than once to require all of them.
+//! - `skip = path`: Not functions with this helper attribute, which gets removed when the items get converted back into tokens.
+//! - `returns = Name`, `()`, `!`, or `impl`: Only functions whose return type is a path ending in `Name` (such as `Result`), that return `()`, that never return, or that return `impl Trait`.
+//!
+//! ```rust,ignore
+//! #[proc_macro_attribute]
+//! pub fn trace_mod(attr: TokenStream, item: TokenStream) -> TokenStream
+//! {
+//! 	// Used like `#[trace_mod(pub, name = "handle_*", returns = Result, skip = no_wrap)]`
+//! 	let filter = parse_macro_input!(attr as FnFilter);
+//! 	let mut item_mod = parse_macro_input!(item as WrappedMod);
+//! 	item_mod.set_filter(filter);
+//! 	...
+//! }
+//! ```
+
+use syn::{Attribute, FnArg, Visibility, Path, ReturnType, Type, Token, LitStr, Ident};
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use quote::ToTokens;
+use crate::{WrappedFn, ERROR_STRS};
+
+/// How a method takes `self`, for filtering functions by their receivers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReceiverKind
+{
+	/// Functions that take `self` in any way.
+	Any,
+	/// Functions that don't take `self`, such as free functions and associated functions.
+	None,
+	/// Methods that take `&self`, including ones written as `self: &Self`.
+	Ref,
+	/// Methods that take `&mut self`, including ones written as `self: &mut Self`.
+	RefMut,
+	/// Methods that take `self` by value, including ones with an explicit type like `self: Box<Self>`.
+	Value
+}
+
+/// The shape of a function's return type, for filtering functions by what they return.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ReturnShape
+{
+	/// Functions that return `()`, either explicitly or by not having a return type.
+	Unit,
+	/// Functions that never return (`-> !`).
+	Never,
+	/// Functions that return an `impl Trait` type.
+	ImplTrait,
+	/// Functions that return a type whose path ends in this name, such as `Result` for `io::Result<()>`.
+	Path(String)
+}
+
+/// Decides which functions get wrapped when wrapping a group of items.
+/// Every function passes the default filter.
+/// Can be parsed from the arguments of an attribute (see the module documentation for the syntax).
+#[derive(Clone, Debug, Default)]
+pub struct FnFilter
+{
+	/// Whether only functions that are declared `pub` pass the filter. `false` by default.
+	/// Methods in trait impls never pass this since they can't be declared `pub`.
+	pub pub_only: bool,
+	/// Whether only `async fn`s (`Some(true)`) or only functions that aren't async (`Some(false)`) pass the filter. `None` by default.
+	pub asyncness: Option<bool>,
+	/// How functions have to take `self` to pass the filter. `None` by default, which lets every function pass.
+	pub receiver: Option<ReceiverKind>,
+	/// Glob patterns that the names of functions have to match one of to pass the filter, where `*` matches any number of characters and `?` matches one. Every name passes if this is empty, which it is by default.
+	pub name_patterns: Vec<String>,
+	/// Paths of attributes that functions have to have all of to pass the filter, such as `inline` or `tokio::test`. Empty by default.
+	pub required_attrs: Vec<String>,
+	/// Path of the helper attribute that keeps a function from passing the filter. `None` by default.
+	/// The attribute gets removed from every function when the items get converted back into tokens, since it isn't a real attribute.
+	pub skip_attr: Option<String>,
+	/// The shape that the return types of functions have to have to pass the filter. `None` by default.
+	pub return_shape: Option<ReturnShape>
+}
+
+impl FnFilter
+{
+	/// Sets whether only functions that are declared `pub` pass the filter.
+	pub fn set_pub_only(&mut self, pub_only: bool)
+	{
+		self.pub_only = pub_only;
+	}
+
+	/// Sets whether only `async fn`s (`true`) or only functions that aren't async (`false`) pass the filter.
+	pub fn set_asyncness(&mut self, asyncness: bool)
+	{
+		self.asyncness = Some(asyncness);
+	}
+
+	/// Removes the asyncness requirement so that functions pass the filter whether they're async or not.
+	pub fn remove_asyncness(&mut self)
+	{
+		self.asyncness = None;
+	}
+
+	/// Sets how functions have to take `self` to pass the filter.
+	pub fn set_receiver(&mut self, receiver: ReceiverKind)
+	{
+		self.receiver = Some(receiver);
+	}
+
+	/// Removes the receiver requirement so that functions pass the filter however they take `self`.
+	pub fn remove_receiver(&mut self)
+	{
+		self.receiver = None;
+	}
+
+	/// Adds a glob pattern that the names of functions can match to pass the filter, where `*` matches any number of characters and `?` matches one.
+	pub fn add_name_pattern(&mut self, pattern: &str)
+	{
+		self.name_patterns.push(pattern.to_string());
+	}
+
+	/// Removes all of the glob patterns so that every name passes the filter again.
+	pub fn remove_name_patterns(&mut self)
+	{
+		self.name_patterns.clear();
+	}
+
+	/// Adds the path of an attribute that functions have to have to pass the filter, such as `inline` for `#[inline]`.
+	pub fn add_required_attr(&mut self, attr: &str)
+	{
+		self.required_attrs.push(attr.to_string());
+	}
+
+	/// Removes all of the required attributes.
+	pub fn remove_required_attrs(&mut self)
+	{
+		self.required_attrs.clear();
+	}
+
+	/// Sets the path of the helper attribute that keeps a function from passing the filter, such as `no_wrap` for `#[no_wrap]`.
+	pub fn set_skip_attr(&mut self, skip_attr: &str)
+	{
+		self.skip_attr = Some(skip_attr.to_string());
+	}
+
+	/// Removes the helper attribute so that it no longer keeps functions from passing the filter.
+	pub fn remove_skip_attr(&mut self)
+	{
+		self.skip_attr = None;
+	}
+
+	/// Sets the shape that the return types of functions have to have to pass the filter.
+	pub fn set_return_shape(&mut self, return_shape: ReturnShape)
+	{
+		self.return_shape = Some(return_shape);
+	}
+
+	/// Removes the return type requirement so that functions pass the filter whatever they return.
+	pub fn remove_return_shape(&mut self)
+	{
+		self.return_shape = None;
+	}
+
+	/// Determines whether a function passes the filter.
+	pub fn matches(&self, function: &WrappedFn) -> bool
+	{
+		let function = &function.function;
+		let sig = &function.sig;
+		// Check the parts of the signature first since they're cheap
+		if self.pub_only && !matches!(function.vis, Visibility::Public(_))
+		{
+			return false;
+		}
+		if self.asyncness.is_some_and(|asyncness| asyncness != sig.asyncness.is_some())
+		{
+			return false;
+		}
+		if self.receiver.is_some_and(|receiver| !receiver_matches(receiver, sig.inputs.first()))
+		{
+			return false;
+		}
+		if self.return_shape.as_ref().is_some_and(|return_shape| !return_shape_matches(return_shape, &sig.output))
+		{
+			return false;
+		}
+		// Functions with the skip attribute never pass, and functions have to have every required attribute
+		if function.attrs.iter().any(|attr| self.is_skip_attr(attr))
+		{
+			return false;
+		}
+		if !self.required_attrs.iter().all(|required| function.attrs.iter().any(|attr| &path_string(attr.path()) == required))
+		{
+			return false;
+		}
+		// The name has to match one of the patterns if there are any
+		let name = sig.ident.to_string();
+		self.name_patterns.is_empty() || self.name_patterns.iter().any(|pattern| glob_matches(pattern, &name))
+	}
+
+	/// Wraps a function if it passes the filter.
+	///
+	/// Inputs:
+	///
+	/// `function`: The function, which can be anything that a `WrappedFn` can be constructed from, such as a `syn::ItemFn` or a `syn::ImplItemFn`.
+	///
+	/// Outputs: The wrapped function if it passes the filter, or `None` if it doesn't.
+	pub fn select<T: Into<WrappedFn>>(&self, function: T) -> Option<WrappedFn>
+	{
+		let function = function.into();
+		self.matches(&function).then_some(function)
+	}
+
+	/// Determines whether an attribute is the helper attribute that keeps functions from passing the filter.
+	pub(crate) fn is_skip_attr(&self, attr: &Attribute) -> bool
+	{
+		self.skip_attr.as_ref().is_some_and(|skip_attr| &path_string(attr.path()) == skip_attr)
+	}
+
+	/// Removes the helper attribute that keeps functions from passing the filter from a list of attributes.
+	pub(crate) fn remove_skip_attrs(&self, attrs: &mut Vec<Attribute>)
+	{
+		attrs.retain(|attr| !self.is_skip_attr(attr));
+	}
+}
+
+/// Allows filters to be parsed from the arguments of attributes, like `#[wrap(pub, name = "handle_*", returns = Result)]`.
+/// Can be constructed using `syn::parse_macro_input` like this:
+///
+/// ```rust,ignore
+/// let filter = parse_macro_input!(attr as FnFilter);
+/// ```
+impl Parse for FnFilter
+{
+	/// Constructs a FnFilter from a comma-separated list of selectors in a `syn::ParseStream`.
+	fn parse(input: ParseStream) -> syn::Result<Self>
+	{
+		let mut filter = FnFilter::default();
+		let selectors = Punctuated::<Selector, Token![,]>::parse_terminated(input)?;
+		for selector in selectors
+		{
+			match selector
+			{
+				Selector::Pub => filter.set_pub_only(true),
+				Selector::Async(asyncness) => filter.set_asyncness(asyncness),
+				Selector::Name(pattern) => filter.add_name_pattern(&pattern),
+				Selector::Receiver(receiver) => filter.set_receiver(receiver),
+				Selector::Attr(attr) => filter.add_required_attr(&attr),
+				Selector::Skip(attr) => filter.set_skip_attr(&attr),
+				Selector::Returns(return_shape) => filter.set_return_shape(return_shape)
+			}
+		}
+		Ok(filter)
+	}
+}
+
+/// One of the comma-separated selectors that a `FnFilter` gets parsed from.
+enum Selector
+{
+	/// `pub`
+	Pub,
+	/// `async` or `!async`
+	Async(bool),
+	/// `name = "pattern"`
+	Name(String),
+	/// `receiver = &self`, `&mut self`, `self`, `_`, or `none`
+	Receiver(ReceiverKind),
+	/// `attr = path`
+	Attr(String),
+	/// `skip = path`
+	Skip(String),
+	/// `returns = Name`, `()`, `!`, or `impl`
+	Returns(ReturnShape)
+}
+
+impl Parse for Selector
+{
+	/// Parses a single selector.
+	fn parse(input: ParseStream) -> syn::Result<Self>
+	{
+		// Selectors without values
+		if input.parse::<Option<Token![pub]>>()?.is_some()
+		{
+			return Ok(Selector::Pub);
+		}
+		if input.parse::<Option<Token![async]>>()?.is_some()
+		{
+			return Ok(Selector::Async(true));
+		}
+		if input.peek(Token![!]) && input.peek2(Token![async])
+		{
+			input.parse::<Token![!]>()?;
+			input.parse::<Token![async]>()?;
+			return Ok(Selector::Async(false));
+		}
+		// Selectors with values
+		let key: Ident = input.parse().map_err(|error| syn::Error::new(error.span(), ERROR_STRS[9]))?;
+		input.parse::<Token![=]>()?;
+		match key.to_string().as_str()
+		{
+			"name" => Ok(Selector::Name(input.parse::<LitStr>()?.value())),
+			"receiver" => Ok(Selector::Receiver(parse_receiver_kind(input)?)),
+			"attr" => Ok(Selector::Attr(path_string(&input.call(Path::parse_mod_style)?))),
+			"skip" => Ok(Selector::Skip(path_string(&input.call(Path::parse_mod_style)?))),
+			"returns" => Ok(Selector::Returns(parse_return_shape(input)?)),
+			_ => Err(syn::Error::new(key.span(), ERROR_STRS[9]))
+		}
+	}
+}
+
+/// Parses the value of a `receiver = ...` selector.
+///
+/// Inputs:
+///
+/// `input`: The tokens after the `=`.
+///
+/// Outputs: The kind of receiver, or an error if it isn't `&self`, `&mut self`, `self`, `_`, or `none`.
+fn parse_receiver_kind(input: ParseStream) -> syn::Result<ReceiverKind>
+{
+	if input.parse::<Option<Token![&]>>()?.is_some()
+	{
+		let mutability: Option<Token![mut]> = input.parse()?;
+		input.parse::<Token![self]>()?;
+		return Ok(if mutability.is_some() { ReceiverKind::RefMut } else { ReceiverKind::Ref });
+	}
+	if input.parse::<Option<Token![self]>>()?.is_some()
+	{
+		return Ok(ReceiverKind::Value);
+	}
+	if input.parse::<Option<Token![_]>>()?.is_some()
+	{
+		return Ok(ReceiverKind::Any);
+	}
+	let ident: Ident = input.parse()?;
+	match ident.to_string().as_str()
+	{
+		"none" => Ok(ReceiverKind::None),
+		_ => Err(syn::Error::new(ident.span(), ERROR_STRS[9]))
+	}
+}
+
+/// Parses the value of a `returns = ...` selector.
+///
+/// Inputs:
+///
+/// `input`: The tokens after the `=`.
+///
+/// Outputs: The shape of the return type, or an error if it isn't a path, `()`, `!`, or `impl`.
+fn parse_return_shape(input: ParseStream) -> syn::Result<ReturnShape>
+{
+	if input.parse::<Option<Token![!]>>()?.is_some()
+	{
+		return Ok(ReturnShape::Never);
+	}
+	if input.parse::<Option<Token![impl]>>()?.is_some()
+	{
+		return Ok(ReturnShape::ImplTrait);
+	}
+	if input.peek(syn::token::Paren)
+	{
+		let content;
+		syn::parenthesized!(content in input);
+		return match content.is_empty()
+		{
+			true => Ok(ReturnShape::Unit),
+			false => Err(content.error(ERROR_STRS[9]))
+		};
+	}
+	let path = input.call(Path::parse_mod_style)?;
+	// Only the last segment of the path is matched, so `Result` matches `io::Result` too
+	let name = path.segments.last().map(|segment| segment.ident.to_string()).unwrap_or_default();
+	Ok(ReturnShape::Path(name))
+}
+
+/// Determines whether the first argument of a function takes `self` in a certain way.
+///
+/// Inputs:
+///
+/// `receiver`: How the function has to take `self`.
+///
+/// `first_arg`: The first argument of the function, if it has any.
+fn receiver_matches(receiver: ReceiverKind, first_arg: Option<&FnArg>) -> bool
+{
+	match (receiver, first_arg)
+	{
+		(ReceiverKind::None, Some(FnArg::Receiver(_))) => false,
+		(ReceiverKind::None, _) => true,
+		(_, Some(FnArg::Receiver(function_receiver))) =>
+		{
+			// Receivers with an explicit type like `self: &Self` don't have a `reference`, so their type has to be checked instead
+			let (by_ref, mutable) = match (&function_receiver.reference, function_receiver.ty.as_ref())
+			{
+				(Some(_), _) => (true, function_receiver.mutability.is_some()),
+				(None, Type::Reference(reference)) => (true, reference.mutability.is_some()),
+				(None, _) => (false, false)
+			};
+			match receiver
+			{
+				ReceiverKind::Ref => by_ref && !mutable,
+				ReceiverKind::RefMut => by_ref && mutable,
+				ReceiverKind::Value => !by_ref,
+				_ => true
+			}
+		},
+		_ => false
+	}
+}
+
+/// Determines whether the return type of a function has a certain shape.
+///
+/// Inputs:
+///
+/// `return_shape`: The shape that the return type has to have.
+///
+/// `output`: The return type of the function.
+fn return_shape_matches(return_shape: &ReturnShape, output: &ReturnType) -> bool
+{
+	let ty = match output
+	{
+		ReturnType::Default => return *return_shape == ReturnShape::Unit,
+		ReturnType::Type(_, ty) => ty.as_ref()
+	};
+	match (return_shape, ty)
+	{
+		(ReturnShape::Unit, Type::Tuple(tuple)) => tuple.elems.is_empty(),
+		(ReturnShape::Never, Type::Never(_)) => true,
+		(ReturnShape::ImplTrait, Type::ImplTrait(_)) => true,
+		(ReturnShape::Path(name), Type::Path(path)) => path.path.segments.last().is_some_and(|segment| segment.ident == name),
+		_ => false
+	}
+}
+
+/// Gets a path as a string without any spaces, such as `tokio::test`, so that attribute paths can be compared.
+fn path_string(path: &Path) -> String
+{
+	path.to_token_stream().to_string().replace(' ', "")
+}
+
+/// Determines whether a name matches a glob pattern, where `*` matches any number of characters and `?` matches one.
+///
+/// Inputs:
+///
+/// `pattern`: The glob pattern.
+///
+/// `name`: The name being matched.
+fn glob_matches(pattern: &str, name: &str) -> bool
+{
+	let pattern: Vec<char> = pattern.chars().collect();
+	let name: Vec<char> = name.chars().collect();
+	// Where to go back to in the pattern and the name if the characters after the last `*` stop matching
+	let mut backtrack: Option<(usize, usize)> = None;
+	let (mut p, mut n) = (0, 0);
+	while n < name.len()
+	{
+		match pattern.get(p)
+		{
+			Some('*') =>
+			{
+				// Try matching nothing with the `*` first
+				backtrack = Some((p, n));
+				p += 1;
+			},
+			Some(&c) if c == '?' || c == name[n] =>
+			{
+				p += 1;
+				n += 1;
+			},
+			_ => match backtrack
+			{
+				// Make the last `*` match one more character and try again
+				Some((star_p, star_n)) =>
+				{
+					backtrack = Some((star_p, star_n + 1));
+					p = star_p + 1;
+					n = star_n + 1;
+				},
+				None => return false
+			}
+		}
+	}
+	// Any `*`s left at the end of the pattern match nothing
+	pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use quote::quote;
+
+	fn function(tokens: proc_macro2::TokenStream) -> WrappedFn
+	{
+		syn::parse2(tokens).unwrap()
+	}
+
+	#[test]
+	fn matches_glob_patterns()
+	{
+		assert!(glob_matches("get_*", "get_name"));
+		assert!(glob_matches("*_mut", "get_mut"));
+		assert!(glob_matches("get_?", "get_x"));
+		assert!(glob_matches("*a*b*", "xaybz"));
+		assert!(!glob_matches("get_?", "get_xy"));
+		assert!(!glob_matches("set_*", "get_name"));
+	}
+
+	#[test]
+	fn filters_functions()
+	{
+		let mut filter = FnFilter::default();
+		filter.set_pub_only(true);
+		filter.set_receiver(ReceiverKind::Any);
+		filter.add_name_pattern("get_*");
+		filter.set_skip_attr("no_wrap");
+		assert!(filter.matches(&function(quote!{ pub fn get_x(&self) -> i32 { self.x } })));
+		assert!(!filter.matches(&function(quote!{ fn get_x(&self) -> i32 { self.x } })));
+		assert!(!filter.matches(&function(quote!{ pub fn get_x() -> i32 { 0 } })));
+		assert!(!filter.matches(&function(quote!{ pub fn set_x(&mut self) {} })));
+		assert!(!filter.matches(&function(quote!{ #[no_wrap] pub fn get_x(&self) -> i32 { self.x } })));
+	}
+
+	#[test]
+	fn filters_typed_receivers_by_how_they_take_self()
+	{
+		let by_ref = function(quote!{ fn get_x(self: &Self) -> i32 { self.x } });
+		let by_ref_mut = function(quote!{ fn set_x(self: &mut Self) {} });
+		let boxed = function(quote!{ fn into_x(self: Box<Self>) -> i32 { self.x } });
+		let mut filter = FnFilter::default();
+		filter.set_receiver(ReceiverKind::Ref);
+		assert!(filter.matches(&by_ref) && !filter.matches(&by_ref_mut) && !filter.matches(&boxed));
+		filter.set_receiver(ReceiverKind::RefMut);
+		assert!(!filter.matches(&by_ref) && filter.matches(&by_ref_mut) && !filter.matches(&boxed));
+		filter.set_receiver(ReceiverKind::Value);
+		assert!(!filter.matches(&by_ref) && !filter.matches(&by_ref_mut) && filter.matches(&boxed));
+	}
+
+	#[test]
+	fn parses_selectors()
+	{
+		let filter: FnFilter = syn::parse_quote!(pub, !async, name = "handle_*", name = "on_*", receiver = &mut self, attr = tracing::instrument, skip = no_wrap, returns = Result);
+		assert!(filter.pub_only);
+		assert_eq!(filter.asyncness, Some(false));
+		assert_eq!(filter.name_patterns, ["handle_*", "on_*"]);
+		assert_eq!(filter.receiver, Some(ReceiverKind::RefMut));
+		assert_eq!(filter.required_attrs, ["tracing::instrument"]);
+		assert_eq!(filter.skip_attr.as_deref(), Some("no_wrap"));
+		assert_eq!(filter.return_shape, Some(ReturnShape::Path("Result".to_string())));
+		assert!(filter.matches(&function(quote!{ #[tracing::instrument] pub fn on_put(&mut self) -> io::Result<()> { Ok(()) } })));
+		assert!(!filter.matches(&function(quote!{ #[tracing::instrument] pub async fn on_put(&mut self) -> io::Result<()> { Ok(()) } })));
+		assert!(!filter.matches(&function(quote!{ #[tracing::instrument] pub fn on_put(&self) -> io::Result<()> { Ok(()) } })));
+		assert!(!filter.matches(&function(quote!{ pub fn on_put(&mut self) -> io::Result<()> { Ok(()) } })));
+		assert!(!filter.matches(&function(quote!{ #[tracing::instrument] pub fn on_put(&mut self) -> Option<()> { None } })));
+		// Return shapes without paths
+		let unit: FnFilter = syn::parse_quote!(returns = (), receiver = none);
+		assert!(unit.matches(&function(quote!{ fn reset() {} })));
+		assert!(unit.matches(&function(quote!{ fn reset() -> () {} })));
+		assert!(!unit.matches(&function(quote!{ fn reset(self) {} })));
+		let never: FnFilter = syn::parse_quote!(returns = !);
+		assert!(never.matches(&function(quote!{ fn exit() -> ! { loop {} } })));
+		// Empty attribute arguments let everything pass
+		let empty: FnFilter = syn::parse_quote!();
+		assert!(empty.select(function(quote!{ fn anything() {} })).is_some());
+		assert!(unit.select(function(quote!{ fn anything() -> u8 { 0 } })).is_none());
+	}
+
+	#[test]
+	fn rejects_unknown_selectors()
+	{
+		let error = syn::parse2::<FnFilter>(quote!{ pub, colour = red }).unwrap_err();
+		assert_eq!(error.to_string(), ERROR_STRS[9]);
+		let error = syn::parse2::<FnFilter>(quote!{ receiver = other }).unwrap_err();
+		assert_eq!(error.to_string(), ERROR_STRS[9]);
+	}
+}
@@ -4,7 +4,7 @@
 //!
 //! This function
 //!
-//! ```rust
+//! ```rust,ignore
 //! #[wrap]
 //! fn hello() -> bool
 //! {
@@ -16,7 +16,7 @@
 //!
 //! which is being wrapped by this attribute
 //!
-//! ```rust
+//! ```rust,ignore
 //! use function_wrapper::WrappedFn;
 //! extern crate proc_macro;
 //! extern crate proc_macro2;
@@ -40,17 +40,17 @@
 //!
 //! will turn into this after being compiled.
 //!
-//! ```rust
+//! ```rust,ignore
 //! fn hello() -> bool
 //! {
 //! 	println!("Hi at the start :)");
-//! 	let mut wrapper = ||
+//! 	let wrapper = || -> bool
 //! 	{
 //! 		println!("Hello there!");
 //! 		println!("This is some code.");
 //! 		true
 //! 	};
-//! 	let result = wrapper();
+//! 	let result = { wrapper }();
 //! 	println!("Hi at the end :)");
 //! 	result
 //! }
@@ -59,19 +59,97 @@
 //! If only pre-code is added, a wrapper closure and extra return expression won't be added since they are unecessary in this case.
 //! If only post-code is added, the wrapper closure and return expression will still be added out of necessity.
 //!
+//! The wrapper closure is given the same return type as the function so that `?` and other conversions in the original code work the same way they did before it was wrapped.
+//! Any `impl Trait` types in the return type are left for the compiler to infer since they can't be named inside of the function.
+//! The closure is moved into a block when it gets called so that it doesn't need a mutable binding, which would trip the `unused_mut` lint whenever the closure doesn't mutate anything.
+//! Functions that return `()` only store their result if the post code uses it.
+//! Lints that the generated code still trips can be allowed with `set_lint_allows`, which only puts them on statements that don't hold the original code.
+//!
+//! The original code keeps its spans, so errors in it point at the same place they did before it was wrapped.
+//! Generated code is located at the function's name, and rewritten `return`s and `?`s are located at the tokens they replace.
+//! Pre-code and post-code can be located somewhere with `set_pre_code_spanned` and `set_post_code_spanned` so that errors in them point there instead of at the attribute.
+//!
+//! If the function is a `const fn`, the original code is put in a labeled block instead of a closure since closures can't be called in const contexts.
+//! The same goes for functions with the `#[track_caller]` attribute, so that `Location::caller()` and panics in the original code still point at the function's caller.
+//! The variable named by a `WrappedFn`'s `caller_ident` (`caller` by default, or `caller_` and so on if the function already uses that name) holds that location in any code added to the function.
+//! Panic catching and cancellation code on a returned `impl Future` would run the original code inside a closure, which loses track of the caller, so they can't be added to `#[track_caller]` functions.
+//!
+//! If the function is an `unsafe fn`, the code added to it can't do unsafe operations outside of its own `unsafe` blocks, in every edition.
+//! Everything inside of an `unsafe fn` but nested items is an unsafe context before the 2024 edition, so the `unsafe fn` passes its arguments to a nested safe function that all of the code is added to.
+//! The original code is put in an `unsafe` block in the nested function, so it keeps the unsafe context it already had.
+//! Nested functions can't use `self`, `Self`, or the generic parameters of an impl block, so code can't be added to `unsafe fn`s that use them.
+//!
+//! Functions that never return (`-> !`) can't have post code since it would never run, but they can still have pre code, finally code, and panic code.
+//!
+//! If the function is an `async fn`, the original code is wrapped in an async block instead of a closure and awaited in place, so `.await` can still be used inside the function and the result variable holds the awaited value.
+//!
+//! Methods in impl blocks and trait definitions can be wrapped the same way as free functions.
+//! A whole impl block can be wrapped with a `WrappedImpl`, which gives out each method that passes its `FnFilter` as a `WrappedFn`.
+//! The filter can pick methods by visibility, by whether they take `self`, by name patterns, or skip ones that have a helper attribute.
+//! Methods that use the impl block's generic parameters, `self`, or `Self` are never moved into nested functions, since those couldn't use them.
+//! Every function in an inline module, including the methods in its impl blocks, the default methods in its trait definitions, and the functions in its nested modules, can be wrapped with a `WrappedMod` in the same way.
+//! Whole source files, such as generated or vendored code that can't have attributes put on it, can be wrapped from build scripts with a `WrappedFile` and written to `OUT_DIR` to be included with `include!`.
+//! Filters can be parsed from attribute arguments like `pub, name = "handle_*", returns = Result, skip = no_wrap` to pick functions by their visibility, asyncness, receiver, attributes, and return type.
+//! Closures, including `move` and `async` closures, can be given pre-code and post-code with a `WrappedClosure`, which keeps their capture mode, parameters, and return type.
+//! The `function-wrapper-preview` command-line tool, which is built with the `preview` feature, prints a source file with pre-code and post-code added to its functions (or a unified diff against the original) so that expansions can be checked without writing a procedural macro.
+//!
+
+#![allow(clippy::tabs_in_doc_comments)]
 
-use proc_macro2::{TokenStream, Span};
-use syn::{ItemFn, Block, Ident, /* Type, ReturnType */};
-use syn::parse::{Parse, ParseStream};
-use syn;
-use quote::{quote, ToTokens};
+use proc_macro2::{TokenStream, TokenTree, Span};
+use syn::{ItemFn, ImplItemFn, TraitItemFn, Token, Block, Ident, Type, ReturnType, TypeParamBound, TypeInfer, Expr, Item, Lifetime, PathArguments, FnArg, Pat, PatIdent, GenericParam};
+use syn::punctuated::Punctuated;
+use syn::parse::{Parse, ParseStream, Parser};
+use syn::visit_mut::{self, VisitMut};
+use syn::visit::{self, Visit};
+use quote::{quote, quote_spanned, ToTokens};
 use core::iter::Extend;
 
+mod fn_filter;
+mod wrapped_impl;
+mod wrapped_mod;
+mod wrapped_file;
+mod wrapped_closure;
+
+pub use fn_filter::{FnFilter, ReceiverKind, ReturnShape};
+pub use wrapped_impl::WrappedImpl;
+pub use wrapped_mod::WrappedMod;
+pub use wrapped_file::WrappedFile;
+pub use wrapped_closure::WrappedClosure;
+
 /// String table of error messages
-const ERROR_STRS: [&str; 1] =
+const ERROR_STRS: [&str; 15] =
 [
 	// Error message for when no tokens are given to parse in the `syn::parse()` method.
-	"expected function"
+	"expected function",
+	// Error message for when async pre-code or post-code is added to a function that isn't async.
+	"async pre-code and post-code can only be added to `async fn`s",
+	// Error message for when cancellation code is added to a function that doesn't return a future.
+	"cancellation code can only be added to `async fn`s and functions that return `impl Future`",
+	// Error message for when panics are nested into the return type of a function that doesn't return a `Result`.
+	"panics can only be nested into the return type of functions that return a `Result`",
+	// Error message for when a panic sentinel is added to a function that doesn't have a foreign ABI.
+	"panic sentinels can only be added to functions with a foreign ABI, such as `extern \"C\" fn`s",
+	// Error message for when code added to a `const fn` uses something that can't be used in const contexts.
+	"closures, async code, `for` loops, `?`, and non-const macros can't be used in code added to a `const fn`",
+	// Error message for when a hook that needs closures or panic catching is added to a `const fn`.
+	"finally code, panic code, panic conversions, and panic sentinels can't be added to a `const fn`",
+	// Error message for when code that needs the function to return is added to a function that never returns.
+	"post code and panic conversions can't be added to functions that never return (`-> !`), but pre code, finally code, and panic code can",
+	// Error message for when a module that isn't inline is wrapped.
+	"only inline modules (`mod name { ... }`) can be wrapped",
+	// Error message for when a function filter is parsed from something that isn't a selector.
+	"expected `pub`, `async`, `!async`, `name = \"pattern\"`, `receiver = &self | &mut self | self | _ | none`, `attr = path`, `skip = path`, or `returns = Name | () | ! | impl`",
+	// Error message for when a woven file is written to `OUT_DIR` outside of a build script.
+	"`OUT_DIR` isn't set, so woven files can only be written to it from build scripts",
+	// Error message for when the original code of a function that gets put in a labeled block can leave it in a way that can't be rewritten to run the post code.
+	"post code wouldn't run after this, since the original code of `const fn`s, `#[track_caller]` functions, and functions using `ExpansionStrategy::Block` is put in a labeled block, and only `return`s and `?`s on `Result`s and `Option`s outside of macros can be rewritten to break out of it",
+	// Error message for when code that needs the original code to run in a closure is added to a `#[track_caller]` function.
+	"`#[track_caller]` functions can't have panic code, panic sentinels, panic conversions, or cancellation code on a returned `impl Future`, since they run the original code in a closure, which doesn't know where the function was called from",
+	// Error message for when code is added to an `unsafe fn` that uses something that the nested function that the code runs in can't.
+	"code can't be added to `unsafe fn`s that use `self`, `Self`, or the generic parameters of their impl block, since it runs in a nested function to keep it out of the function's unsafe context",
+	// Error message for when a woven file that can't be included with `include!` is written.
+	"files with inner attributes (`#![...]` or `//!`) or a shebang can't be included with `include!`, so they can't be written as woven files"
 ];
 
 // /// Contains the type variants that wrapped function can return.
@@ -84,12 +162,86 @@ const ERROR_STRS: [&str; 1] =
 // 	Type(Type)
 // }
 
+/// The ways that the original code of a function can be wrapped when code is added after it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ExpansionStrategy
+{
+	/// The original code is moved into a closure (or an async block for `async fn`s) that gets called and stored in the result variable.
+	/// `return`s and `?`s in the original code exit the closure, so post-code always runs after them.
+	///
+	/// `const fn`s can't call closures, so they use `ExpansionStrategy::Block` instead.
+	#[default]
+	Closure,
+	/// The original code is put in a labeled block that is stored in the result variable, so it is never moved into a closure.
+	/// This lets functions that return references derived from their parameters (such as `&mut` accessors) be wrapped, since the references don't have to escape a closure.
+	///
+	/// `return`s in the original code are rewritten to break out of the block so that post-code still runs after them.
+	/// `?`s are rewritten the same way if the function returns a `Result` or an `Option`, and otherwise still return from the function directly.
+	/// `return`s and `?`s inside of macro invocations can't be seen and aren't rewritten.
+	Block,
+	/// The original code is moved into a function nested inside of the wrapped function that has the same generics, where-clauses, parameters, and return type.
+	/// The wrapped function calls it and stores what it returns in the result variable.
+	/// This avoids closure capture problems, keeps `return`s and `?`s returning from a real function, and makes the generated code easy to read.
+	///
+	/// Parameters that are destructured with patterns are rebuilt from their bindings to be passed to the nested function.
+	/// Parameters whose patterns can't be rebuilt (such as `_` or patterns with `..`) are renamed in the wrapped function, so pre-code and post-code can't use their bindings.
+	///
+	/// Nested functions can't use `self`, `Self`, or the generic parameters of a surrounding `impl` block or trait.
+	/// Functions that use `self`, `Self`, or the generic parameters of their impl block (see `WrappedFn::outer_generics`) fall back to `ExpansionStrategy::Closure` (or `ExpansionStrategy::Block` for `const fn`s).
+	InnerFn
+}
+
+/// The kinds of function items that a `WrappedFn` can be made from.
+/// A `WrappedFn` gets converted back into the same kind of item when it's converted into a `proc_macro2::TokenStream`.
+#[derive(Clone, Debug, Default)]
+pub enum FnItemKind
+{
+	/// A free function (`syn::ItemFn`). Methods in trait definitions that have a default implementation look the same as these.
+	#[default]
+	Fn,
+	/// A method in an impl block (`syn::ImplItemFn`), which can be marked `default`.
+	ImplFn
+	{
+		/// The `default` keyword, if the method had it.
+		defaultness: Option<Token![default]>
+	},
+	/// A method in a trait definition (`syn::TraitItemFn`).
+	TraitFn
+	{
+		/// Whether the method has a default implementation. Methods without one don't have any code to wrap.
+		has_default: bool
+	}
+}
+
+/// The ways that a panic in the original code of a function can be converted into an error that the function returns.
+/// The variable named by a `WrappedFn`'s `payload_ident` holds the boxed panic payload (`Box<dyn Any + Send>`) in conversion code, and the variable named by its `fn_name_ident` holds the name of the function.
+#[derive(Clone, Debug)]
+pub enum PanicConversion
+{
+	/// Changes the function's return type from `T` to `Result<T, Box<dyn Any + Send>>` and returns panic payloads as errors.
+	Payload,
+	/// Changes the function's return type from `T` to `Result<T, error_type>` and returns the result of running `convert` as the error when the function panics.
+	Wrap
+	{
+		/// The type of error that panics are converted into.
+		error_type: Box<Type>,
+		/// An expression that converts the panic payload into an error.
+		convert: TokenStream
+	},
+	/// Keeps the function's return type, which must already be a `Result`, and returns the result of running `convert` as the error when the function panics.
+	Nest
+	{
+		/// An expression that converts the panic payload into the function's error type.
+		convert: TokenStream
+	}
+}
+
 /// Function that can have code inserted before and after the rest of the function executes.
 /// Can be constructed with `syn::parse()` and other variations of parsing from the `syn` crate.
 ///
 /// Example:
 ///
-/// ```rust
+/// ```rust,ignore
 /// let mut function = parse_macro_input!(token_stream as WrappedFn);
 /// ```
 ///
@@ -97,7 +249,7 @@ const ERROR_STRS: [&str; 1] =
 ///
 /// Example:
 ///
-/// ```rust
+/// ```rust,ignore
 /// function.set_pre_code(quote!{ println!("Hi at the start :)"); });
 /// ```
 ///
@@ -105,9 +257,71 @@ const ERROR_STRS: [&str; 1] =
 ///
 /// Example:
 ///
-/// ```rust
+/// ```rust,ignore
 /// function.set_post_code(quote!{ println!("Hi at the end :)"); });
 /// ```
+///
+/// Code that is allowed to `.await` can be set on `async fn`s using the `set_async_pre_code()` and `set_async_post_code()` methods.
+/// Async pre-code runs after the normal pre-code and async post-code runs before the normal post-code.
+///
+/// Example:
+///
+/// ```rust,ignore
+/// function.set_async_post_code(quote!{ buffer.flush().await; })?;
+/// ```
+///
+/// Code that runs if the future of an `async fn` or a function that returns `impl Future` is dropped before it completes can be set using the `set_cancel_code()` method.
+///
+/// Example:
+///
+/// ```rust,ignore
+/// function.set_cancel_code(quote!{ println!("Request abandoned :("); })?;
+/// ```
+///
+/// Code that runs when the function panics can be set using the `set_panic_code()` method.
+/// The panic keeps unwinding after this code runs.
+///
+/// Example:
+///
+/// ```rust,ignore
+/// let fn_name = &function.fn_name_ident;
+/// function.set_panic_code(quote!{ eprintln!("Panicked in {}", #fn_name); });
+/// ```
+///
+/// Panics can be turned into errors that the function returns using the `set_panic_conversion()` method.
+///
+/// Example:
+///
+/// ```rust,ignore
+/// let payload = &function.payload_ident;
+/// function.set_panic_conversion(PanicConversion::Nest{ convert: quote!{ PluginError::Panicked(#payload) } })?;
+/// ```
+///
+/// Functions with a foreign ABI (such as `extern "C" fn`s) can be given a value to return when they panic using the `set_panic_sentinel()` method.
+/// This stops panics from unwinding across the FFI boundary.
+///
+/// Example:
+///
+/// ```rust,ignore
+/// function.set_panic_sentinel(quote!{ -1 })?;
+/// ```
+///
+/// Code that runs however the function exits, including by panicking, can be set using the `set_finally_code()` method.
+///
+/// Example:
+///
+/// ```rust,ignore
+/// let panicking = &function.panicking_ident;
+/// function.set_finally_code(quote!{ println!("Exited (panicked: {})", #panicking); });
+/// ```
+///
+/// How the original code of the function gets wrapped when code is added after it can be changed with the `set_expansion_strategy()` method.
+///
+/// Example:
+///
+/// ```rust,ignore
+/// function.set_expansion_strategy(ExpansionStrategy::Block);
+/// ```
 #[derive(Clone, Debug)]
 pub struct WrappedFn
 {
@@ -117,14 +331,54 @@ pub struct WrappedFn
 	pub pre_code: Option<TokenStream>,
 	/// Contains code that gets run after the rest of the function.
 	pub post_code: Option<TokenStream>,
+	/// Contains code that gets run before the rest of an async function and is allowed to `.await`.
+	pub async_pre_code: Option<TokenStream>,
+	/// Contains code that gets run after the rest of an async function and is allowed to `.await`.
+	pub async_post_code: Option<TokenStream>,
+	/// Contains code that gets run if the function's future is dropped while the original code of the function is still running.
+	pub cancel_code: Option<TokenStream>,
+	/// Contains code that gets run when the function exits by any path, including returning early and unwinding from a panic.
+	pub finally_code: Option<TokenStream>,
+	/// Contains code that gets run when the original code of the function panics, before the panic keeps unwinding.
+	pub panic_code: Option<TokenStream>,
+	/// How panics in the original code of the function get converted into errors that the function returns instead of unwinding. Panics aren't converted by default.
+	pub panic_conversion: Option<PanicConversion>,
+	/// Value that a function with a foreign ABI returns instead of unwinding when its original code panics.
+	pub panic_sentinel: Option<TokenStream>,
 	// /// The arguments to the function.
 	// pub args: Vec<FnArgData>,
 	// /// Return type.
 	// pub output: WrappedFnOutput,
+	/// How the original code of the function gets wrapped when code is added after it. `ExpansionStrategy::Closure` by default.
+	pub expansion_strategy: ExpansionStrategy,
 	/// Identifier token for the closure that wraps all of the original code from the wrapped function. `wrapper` by default.
+	/// With `ExpansionStrategy::Block`, this is used as the label of the block instead.
+	/// With `ExpansionStrategy::InnerFn`, this is used as the name of the nested function instead.
 	pub wrapper_ident: Ident,
 	/// Identifier token for the variable that holds the return value of the wrapped function. `result` by default.
-	pub result_ident: Ident
+	/// If the function already uses `wrapper` or `result`, underscores are added to the end of the default identifiers until they don't collide with anything in it.
+	pub result_ident: Ident,
+	/// Lints that are allowed on the statements that only hold generated code, such as `clippy::let_unit_value`. None by default.
+	/// Statements that hold the original code of the function don't get these, so its own lints stay active.
+	pub lint_allows: Option<TokenStream>,
+	/// The kind of function item that the function was made from and gets converted back into. `FnItemKind::Fn` by default.
+	pub item_kind: FnItemKind,
+	/// The generic parameters of the impl block or trait that the function is in, if it's a method. Empty by default.
+	/// Nested functions can't use these, so `ExpansionStrategy::InnerFn` falls back to `ExpansionStrategy::Closure` when the function uses any of them.
+	pub outer_generics: syn::Generics,
+	/// Whether the wrapper and result identifiers are hygienic (`Span::mixed_site()`), so that they can't collide with or be seen by anything in the original code. `false` by default.
+	/// Code added to the function can only use the result variable through the identifier from `get_result_ident` when this is set.
+	pub hygienic_idents: bool,
+	/// Identifier token for the variable that tells finally code whether the function is exiting because of a panic. `panicking` by default.
+	pub panicking_ident: Ident,
+	/// Identifier token for the variable that holds a reference to the panic payload (`&(dyn Any + Send)`) in panic code. `payload` by default.
+	pub payload_ident: Ident,
+	/// Identifier token for the variable that holds the name of the function (`&'static str`) in panic code. `fn_name` by default.
+	pub fn_name_ident: Ident,
+	/// Identifier token for the variable that holds the location that the function was called from (`&'static Location<'static>`). `caller` by default, with `_`s added until nothing in the function uses it.
+	/// This can be used in any code added to the function, but it's only the location of the function's caller if the function has the `#[track_caller]` attribute.
+	/// The variable is declared at the start of the function, so the original code can't use it as long as the identifier doesn't collide with anything in it.
+	pub caller_ident: Ident
 }
 
 impl WrappedFn
@@ -141,241 +395,2827 @@ impl WrappedFn
 		self.post_code = Some(post_code);
 	}
 
-	/// Removes any code that was going to be added before the rest of the function.
-	pub fn remove_pre_code(&mut self)
+	/// Sets the code that gets run before the rest of the function executes if it parses as statements.
+	///
+	/// Outputs: An error pointing at the tokens that kept the code from parsing, which can be turned into a `compile_error!` with `syn::Error::to_compile_error()`.
+	pub fn try_set_pre_code(&mut self, pre_code: TokenStream) -> syn::Result<()>
 	{
-		self.pre_code = None;
+		check_stmts(&pre_code)?;
+		self.pre_code = Some(pre_code);
+		Ok(())
 	}
 
-	/// Removes any code that was going to be added after the rest of the function.
-	pub fn remove_post_code(&mut self)
+	/// Sets the code that gets run after the rest of the function executes if it parses as statements.
+	///
+	/// Outputs: An error pointing at the tokens that kept the code from parsing, which can be turned into a `compile_error!` with `syn::Error::to_compile_error()`.
+	pub fn try_set_post_code(&mut self, post_code: TokenStream) -> syn::Result<()>
 	{
-		self.post_code = None;
+		check_stmts(&post_code)?;
+		self.post_code = Some(post_code);
+		Ok(())
 	}
 
-	/// Changes the identifier for the closure that wraps the code of the original function (`wrapper` by default).
-	pub fn set_wrapper_ident(&mut self, ident: &str)
+	/// Sets the code that gets run before the rest of the function executes, located at the given span so that errors in it point there.
+	/// Names in the code still resolve the same way, since only the location of its spans changes.
+	pub fn set_pre_code_spanned(&mut self, pre_code: TokenStream, span: Span)
 	{
-		self.wrapper_ident = Ident::new(ident, Span::call_site());
+		self.pre_code = Some(locate_tokens(pre_code, span));
 	}
 
-	/// Changes the identifier for the variable that holds the value that the function returns (`result` by default).
-	pub fn set_result_ident(&mut self, ident: &str)
+	/// Sets the code that gets run after the rest of the function executes, located at the given span so that errors in it point there.
+	/// Names in the code still resolve the same way, since only the location of its spans changes.
+	pub fn set_post_code_spanned(&mut self, post_code: TokenStream, span: Span)
 	{
-		self.result_ident = Ident::new(ident, Span::call_site());
+		self.post_code = Some(locate_tokens(post_code, span));
 	}
 
-	/// Inserts the unwrapped original code from a function into a function block.
-	///
-	/// Inputs:
-	/// 
-	/// `function_block`: The block of code that goes inside the function where the original code is re-added.
+	/// Sets the code that gets run before the rest of an async function executes.
+	/// This code is allowed to `.await`.
 	///
-	/// `og_code`: The original code from the function.
-	fn add_unwrapped_code(function_block: &mut TokenStream, og_code: &Block)
+	/// Returns an error if the function is not an `async fn`.
+	pub fn set_async_pre_code(&mut self, async_pre_code: TokenStream) -> syn::Result<()>
 	{
-		// Convert the function's old code block into a TokenStream and add it after the pre code
-		function_block.extend(quote!{ #og_code });
+		self.check_async()?;
+		self.async_pre_code = Some(async_pre_code);
+		Ok(())
 	}
 
-	/// Wraps the original code of a function in a closure and inserts code after it inside a function block.
-	///
-	/// Inputs:
-	///
-	/// `og_code`: The original code of the function that comes before the post code.
+	/// Sets the code that gets run after the rest of an async function executes.
+	/// This code is allowed to `.await`.
 	///
-	/// `wrapper_ident`: Identifier token for the closure that wraps all of the original code from the wrapped function.
+	/// Returns an error if the function is not an `async fn`.
+	pub fn set_async_post_code(&mut self, async_post_code: TokenStream) -> syn::Result<()>
+	{
+		self.check_async()?;
+		self.async_post_code = Some(async_post_code);
+		Ok(())
+	}
+
+	/// Sets the code that gets run if the future of an `async fn` or a function that returns `impl Future` is dropped before the original code of the function finishes.
+	/// Post-code only runs if the future completes, so this is where resources held by an abandoned future can be released.
 	///
-	/// `result_ident`: Identifier token for the variable that holds the return value of the wrapped function.
+	/// The code is run inside of a closure that is dropped with the future.
+	/// It doesn't run if the future gets dropped while a panic is unwinding, since that isn't a cancellation.
+	/// The closure borrows anything that this code uses until the future completes or gets dropped, so in an `async fn` the original code can't move or mutably borrow it.
 	///
-	/// `function_block`: The block of code that goes inside the function where the wrapper code and post code is added.
+	/// In functions that return `impl Future`, the returned future is wrapped in another future, and any parameters that this code or the post-code uses are moved into it.
+	/// The post-code of those functions runs inside of the new future once the original future completes, instead of when the function returns it, and the variable named by `result_ident` holds what the future evaluated to.
 	///
-	/// `post_code`: The code to be inserted that runs at the end of the function.
-	fn add_wrapped_post_code(og_code: &Block, wrapper_ident: &Ident, result_ident: &Ident, function_block: &mut TokenStream, post_code: &TokenStream)
+	/// Returns an error if the function is not an `async fn` and does not return `impl Future`.
+	pub fn set_cancel_code(&mut self, cancel_code: TokenStream) -> syn::Result<()>
 	{
-		// Wrap the code in a closure, get the result of running that closure, and turn all of it into a TokenStream
-		let wrapper_code = quote!
+		// Make sure the function actually produces a future that can be dropped early
+		if self.function.sig.asyncness.is_none() && !self.returns_impl_future()
 		{
-			let mut #wrapper_ident = || #og_code ;
-			let #result_ident = #wrapper_ident ();
-		};
-		// Get a TokenStream of the return line
-		let return_line = quote!{ #result_ident };
-		// Add the wrapped code that came with the function
-		function_block.extend(wrapper_code);
-		// Add the code that runs after the rest of the function
-		function_block.extend(post_code.clone());
-		// Add the line that returns the return value
-		function_block.extend(return_line);
+			return Err(syn::Error::new(self.function.sig.fn_token.span, ERROR_STRS[2]));
+		}
+		self.cancel_code = Some(cancel_code);
+		Ok(())
 	}
 
-	/// Gets a `syn::ItemFn` of a function that just had code inserted into it.
+	/// Sets the code that gets run when the function exits by any path: returning normally, returning early with `return` or `?`, or unwinding from a panic.
+	/// In async functions, this code also runs if the function's future is dropped before it completes.
+	/// The variable named by `panicking_ident` can be used in this code to tell whether the function is exiting because of a panic.
 	///
-	/// Inputs:
-	///
-	/// `function`: The original function that is being wrapped.
-	///
-	/// `function_block`: The new block of code that is replacing the old one inside the function that is being wrapped.
+	/// The code is run inside of a closure held by a guard that gets dropped when the function exits.
+	/// Anything that this code uses is borrowed (or moved) by that closure for the rest of the function after the pre-code, so the original code can't move or mutably borrow it.
+	pub fn set_finally_code(&mut self, finally_code: TokenStream)
+	{
+		self.finally_code = Some(finally_code);
+	}
+
+	/// Sets the code that gets run when the original code of the function panics.
+	/// The panic is resumed with the same payload after this code runs, so the function's signature and behavior don't change.
+	/// The variables named by `payload_ident` and `fn_name_ident` can be used in this code to get the panic payload and the name of the function.
 	///
-	/// Outputs: A `syn::ItemFn` of the newly wrapped function.
-	fn get_wrapped_function(function: &ItemFn, function_block: &TokenStream) -> ItemFn
+	/// The original code is run in a closure passed to `std::panic::catch_unwind()` (or polled inside of one if the function is async) and is asserted to be unwind safe since the panic is never swallowed.
+	/// Since the code is moved into a closure, functions that return references derived from their parameters can't have panic code.
+	pub fn set_panic_code(&mut self, panic_code: TokenStream)
 	{
-		// Wrap all of this code inside curly braces
-		let function_block = quote!{ { #function_block } };
-		// Creates a clone of the function
-		let mut new_function = function.clone();
-		// Put the new code block inside the new function
-		new_function.block = syn::parse(function_block.into()).unwrap();
-		// Return the function with the new code
-		new_function
+		self.panic_code = Some(panic_code);
 	}
-}
 
-/// Main way to construct a `WrappedFn`.
-/// Can be constructed using `syn::parse_macro_input` like this:
-///
-/// ```rust
-/// let mut function = parse_macro_input!(token_stream as WrappedFn);
-/// ```
-impl Parse for WrappedFn
-{
-	/// Constructs a WrappedFn from a `syn::ParseStream`.
-	fn parse(input: ParseStream) -> syn::Result<Self>
+	/// Removes any code that was going to be added before the rest of the function.
+	pub fn remove_pre_code(&mut self)
 	{
-		// If no tokens were given to parse, throw an error
-		if input.is_empty()
-		{
-			return Err(syn::Error::new(input.span(), ERROR_STRS[0]))
-		}
-		// Attempt to parse the input tokens as a function
-		let function: ItemFn = input.parse()?;
-		// Get the return type
-		// let output = match function.sig.output.clone()
-		// {
-		// 	// If a return type was explicitly given, extract it
-		// 	ReturnType::Type(_, o) => WrappedFnOutput::Type(*o),
-		// 	// If no return type was given, use the default return type variant (usually represented as ())
-		// 	ReturnType::Default => WrappedFnOutput::Default
-		// };
-		// Construct a WrappedFn to return
-		let wrapped_function = Self
-		{
-			function: function,
-			pre_code: None,
-			post_code: None,
-			// output: output,
-			wrapper_ident: Ident::new("wrapper", Span::call_site()),
-			result_ident: Ident::new("result", Span::call_site())
-		};
-		Ok(wrapped_function)
+		self.pre_code = None;
 	}
-}
 
-/// Allows `WrappedFn`s to be converted to `syn::ItemFn`s for easier use in procedural macros.
-impl From<&WrappedFn> for ItemFn
-{
-	/// Converts a `WrappedFn` into a `syn::ItemFn`.
-	fn from(function: &WrappedFn) -> Self
+	/// Removes any code that was going to be added after the rest of the function.
+	pub fn remove_post_code(&mut self)
 	{
-		// Determine whether the function has code that gets run before / after the rest of the function or not
-		match (&function.pre_code, &function.post_code)
+		self.post_code = None;
+	}
+
+	/// Removes any async code that was going to be added before the rest of the function.
+	pub fn remove_async_pre_code(&mut self)
+	{
+		self.async_pre_code = None;
+	}
+
+	/// Removes any async code that was going to be added after the rest of the function.
+	pub fn remove_async_post_code(&mut self)
+	{
+		self.async_post_code = None;
+	}
+
+	/// Removes any code that was going to run when the function's future is dropped before completing.
+	pub fn remove_cancel_code(&mut self)
+	{
+		self.cancel_code = None;
+	}
+
+	/// Removes any code that was going to run when the function exits.
+	pub fn remove_finally_code(&mut self)
+	{
+		self.finally_code = None;
+	}
+
+	/// Removes any code that was going to run when the function panics.
+	pub fn remove_panic_code(&mut self)
+	{
+		self.panic_code = None;
+	}
+
+	/// Sets how panics in the original code of the function get converted into errors that the function returns.
+	/// Panics are caught instead of unwinding out of the function, and the panic code (if there is any) runs before the conversion.
+	///
+	/// `PanicConversion::Payload` and `PanicConversion::Wrap` change the function's return type from `T` to `Result<T, E>`.
+	/// `PanicConversion::Nest` keeps the function's return type, which has to be a `Result` already.
+	///
+	/// The original code is asserted to be unwind safe, so the caller has to make sure nothing it uses is left in a broken state after a panic.
+	///
+	/// Returns an error if `PanicConversion::Nest` is used on a function that doesn't return a `Result`.
+	pub fn set_panic_conversion(&mut self, conversion: PanicConversion) -> syn::Result<()>
+	{
+		// Make sure there is a `Result` to nest the errors into
+		if let PanicConversion::Nest{..} = conversion
 		{
-			// If the function has some code to get run both before and after the function
-			(Some(pre_code), Some(post_code)) =>
-			{
-				// Create a new block of code that will replace the old one in the function and start it with the pre code
-				let mut function_block = pre_code.clone();
-				// Wrap and add the original function code and add the post code to the new function block
-				WrappedFn::add_wrapped_post_code(&function.function.block, &function.wrapper_ident, &function.result_ident, &mut function_block, post_code);
-				// Replaces the function's code block with the new one and returns the function
-				WrappedFn::get_wrapped_function(&function.function, &function_block)
-			},
-			// If the function has some code to get run before the function but not after
-			(Some(pre_code), None) =>
+			if ReturnRewriter::get_try_kind(&self.function.sig.output) != TryKind::Result
 			{
-				// Create a new block of code that will replace the old one in the function and start it with the pre code
-				let mut function_block = pre_code.clone();
-				// Add the function's original code after the pre code
-				WrappedFn::add_unwrapped_code(&mut function_block, &function.function.block);
-				// Replaces the function's code block with the new one and returns the function
-				WrappedFn::get_wrapped_function(&function.function, &function_block)
-			},
-			// If the function has some code to get run after the function but not before
-			(None, Some(post_code)) =>
-			{
-				// Create a new block of code that will replace the old one in the function
-				let mut function_block = TokenStream::new();
-				// Wrap and add the original function code and add the post code to the new function block
-				WrappedFn::add_wrapped_post_code(&function.function.block, &function.wrapper_ident, &function.result_ident, &mut function_block, post_code);
-				// Replaces the function's code block with the new one and returns the function
-				WrappedFn::get_wrapped_function(&function.function, &function_block)
-			},
-			// If the function has no code to insert anywhere
-			(None, None) =>
-			{
-				// Just return the function the way it is
-				function.function.clone()
+				return Err(syn::Error::new(self.function.sig.fn_token.span, ERROR_STRS[3]));
 			}
 		}
+		self.panic_conversion = Some(conversion);
+		Ok(())
 	}
-}
 
-/// Allows `WrappedFn`s to be converted to `syn::ItemFn`s for easier use in procedural macros.
-impl From<WrappedFn> for ItemFn
-{
-	/// Converts a `WrappedFn` into a `syn::ItemFn`.
-	fn from(function: WrappedFn) -> Self
+	/// Stops converting panics into errors so that they unwind out of the function again.
+	pub fn remove_panic_conversion(&mut self)
 	{
-		// Use the `From<&WrappedFn> for ItemFn` implementation
-		Self::from(&function)
+		self.panic_conversion = None;
 	}
-}
 
-/// Allows `WrappedFn`s to be converted into `proc_macro2::TokenStream`s for easier use in procedural attribute macros.
-impl From<&WrappedFn> for TokenStream
-{
-	/// Converts a `WrappedFn` into a `proc_macro2::TokenStream`.
-	fn from(function: &WrappedFn) -> Self
+	/// Sets the value that a function with a foreign ABI (such as an `extern "C" fn`) returns when its original code panics, such as `-1` or a null pointer.
+	/// Panics are caught instead of unwinding across the FFI boundary, and the panic code (if there is any) runs before the value is returned, so it can be used to record the error.
+	/// The variables named by `payload_ident` and `fn_name_ident` can be used in the sentinel code the same way as in panic code.
+	/// This takes priority over any panic conversion, since the function's return type can't change.
+	///
+	/// The original code is asserted to be unwind safe, so the caller has to make sure nothing it uses is left in a broken state after a panic.
+	///
+	/// Returns an error if the function doesn't have a foreign ABI.
+	pub fn set_panic_sentinel(&mut self, sentinel: TokenStream) -> syn::Result<()>
+	{
+		// Make sure the function can actually be called from across an FFI boundary
+		if !self.has_foreign_abi()
+		{
+			return Err(syn::Error::new(self.function.sig.fn_token.span, ERROR_STRS[4]));
+		}
+		self.panic_sentinel = Some(sentinel);
+		Ok(())
+	}
+
+	/// Removes the value that the function was going to return when it panics, so that panics unwind out of it again.
+	pub fn remove_panic_sentinel(&mut self)
 	{
-		// Convert the function to a `syn::ItemFn` and then convert that into a `TokenStream`
-		let function = ItemFn::from(function);
-		quote!{ #function }
+		self.panic_sentinel = None;
 	}
-}
 
-/// Allows `WrappedFn`s to be converted into `proc_macro2::TokenStream`s for easier use in procedural attribute macros.
-impl From<WrappedFn> for TokenStream
-{
-	/// Converts a `WrappedFn` into a `proc_macro2::TokenStream`.
-	fn from(function: WrappedFn) -> Self
+	/// Changes how the original code of the function gets wrapped when code is added after it (`ExpansionStrategy::Closure` by default).
+	pub fn set_expansion_strategy(&mut self, strategy: ExpansionStrategy)
 	{
-		// Convert the function to a `syn::ItemFn` and then convert that into a TokenStream
-		let function = ItemFn::from(function);
-		quote!{ #function }
+		self.expansion_strategy = strategy;
 	}
-}
 
-/// Allows `WrappedFn`s to be easily appended to and converted to `proc_macro2::TokenStream`s.
-impl ToTokens for WrappedFn
-{
-	/// Adds a `WrappedFn` to the end of a `proc_macro2::TokenStream`.
-	fn to_tokens(&self, tokens: &mut TokenStream)
+	/// Changes the identifier for the closure that wraps the code of the original function (`wrapper` by default).
+	pub fn set_wrapper_ident(&mut self, ident: &str)
 	{
-		// Convert the function into a TokenStream
-		let function: TokenStream = self.into();
-		// Add the function to the end of the TokenStream
-		tokens.extend(function);
+		self.wrapper_ident = Ident::new(ident, Span::call_site());
 	}
-}
 
-#[cfg(test)]
-mod tests
-{
-	use super::*;
+	/// Changes the identifier for the variable that holds the value that the function returns (`result` by default).
+	pub fn set_result_ident(&mut self, ident: &str)
+	{
+		self.result_ident = Ident::new(ident, Span::call_site());
+	}
+
+	/// Sets the lints that are allowed on the statements that only hold generated code, as a comma-separated list like `clippy::let_unit_value, unused_variables`.
+	/// The original code of the function doesn't get these, so its own lints stay active.
+	pub fn set_lint_allows(&mut self, lint_allows: TokenStream)
+	{
+		self.lint_allows = Some(lint_allows);
+	}
+
+	/// Removes the lints that are allowed on the statements that only hold generated code.
+	pub fn remove_lint_allows(&mut self)
+	{
+		self.lint_allows = None;
+	}
+
+	/// Gets the attribute that allows the lints in `lint_allows`, or nothing if there aren't any.
+	fn get_lint_allows(&self) -> TokenStream
+	{
+		get_lint_allows_attr(&self.lint_allows)
+	}
+
+	/// Sets the generic parameters of the impl block or trait that the function is in, so that nested functions aren't used when the function uses any of them.
+	pub fn set_outer_generics(&mut self, generics: syn::Generics)
+	{
+		self.outer_generics = generics;
+	}
+
+	/// Changes whether the wrapper and result identifiers are hygienic (`Span::mixed_site()`) so that they can't collide with anything in the original code (not hygienic by default).
+	pub fn set_hygienic_idents(&mut self, hygienic: bool)
+	{
+		self.hygienic_idents = hygienic;
+	}
+
+	/// Gets the identifier token for the variable that holds the value that the function returns, which post code can use to get the return value.
+	/// This has the span that the variable actually gets declared with, so it still works when the identifiers are hygienic.
+	pub fn get_result_ident(&self) -> Ident
+	{
+		self.get_hygienic_ident(&self.result_ident)
+	}
+
+	/// Gets the identifier token for the closure, label, or nested function that wraps the original code of the function, with the span that it actually gets declared with.
+	fn get_wrapper_ident(&self) -> Ident
+	{
+		self.get_hygienic_ident(&self.wrapper_ident)
+	}
+
+	/// Gets an identifier token with a `Span::mixed_site()` span if the wrapper and result identifiers are hygienic, or the identifier token unchanged if they aren't.
+	/// Either way, the identifier token is located at the function's name so that errors about it point there.
+	fn get_hygienic_ident(&self, ident: &Ident) -> Ident
+	{
+		get_hygienic_ident(ident, self.hygienic_idents, self.get_glue_span())
+	}
+
+	/// Gets the span that the code generated to wrap the function is given, so that errors in it point at the function's name instead of the attribute that wrapped it.
+	/// The span resolves names the same way as `Span::call_site()`.
+	fn get_glue_span(&self) -> Span
+	{
+		Span::call_site().located_at(self.function.sig.ident.span())
+	}
+
+	/// Returns an error pointing at the function's signature if the function is not an `async fn`.
+	fn check_async(&self) -> syn::Result<()>
+	{
+		match self.function.sig.asyncness
+		{
+			Some(_) => Ok(()),
+			None => Err(syn::Error::new(self.function.sig.fn_token.span, ERROR_STRS[1]))
+		}
+	}
+
+	/// Determines whether the function has an ABI other than the Rust ABI, such as `extern "C"`.
+	fn has_foreign_abi(&self) -> bool
+	{
+		match &self.function.sig.abi
+		{
+			Some(abi) => match &abi.name
+			{
+				Some(name) => name.value() != "Rust",
+				// `extern fn`s without an ABI name use the C ABI
+				None => true
+			},
+			None => false
+		}
+	}
+
+	/// Determines whether the function is a non-async function whose return type is `impl Future`.
+	fn returns_impl_future(&self) -> bool
+	{
+		// Async functions return `impl Future` implicitly, so they aren't counted here
+		if self.function.sig.asyncness.is_some()
+		{
+			return false;
+		}
+		match &self.function.sig.output
+		{
+			// Look for a `Future` trait bound in an `impl Trait` return type
+			ReturnType::Type(_, output) => match output.as_ref()
+			{
+				Type::ImplTrait(impl_trait) => impl_trait.bounds.iter().any(|bound| match bound
+				{
+					TypeParamBound::Trait(bound) => bound.path.segments.last().is_some_and(|segment| segment.ident == "Future"),
+					_ => false
+				}),
+				_ => false
+			},
+			ReturnType::Default => false
+		}
+	}
+
+	/// Gets code that creates a guard which runs some code when it gets dropped, unless it gets disarmed first.
+	///
+	/// Inputs:
+	///
+	/// `guard_ident`: Identifier token for the variable that holds the guard.
+	///
+	/// `code`: The code that runs when the guard gets dropped.
+	///
+	/// `disarmable`: Whether the guard can be disarmed, which requires it to be mutable.
+	///
+	/// Outputs: The statement that creates the guard.
+	fn get_drop_guard(guard_ident: &Ident, code: &TokenStream, disarmable: bool) -> TokenStream
+	{
+		let mutability = match disarmable
+		{
+			true => quote!{ mut },
+			false => TokenStream::new()
+		};
+		// The guard type is declared inside of a block so that multiple guards in one function don't collide
+		let guard_type = Ident::new("Guard", Span::mixed_site());
+		let hook = Ident::new("hook", Span::mixed_site());
+		quote!
+		{
+			let #mutability #guard_ident =
+			{
+				struct #guard_type<F: ::core::ops::FnOnce()>(::core::option::Option<F>);
+				impl<F: ::core::ops::FnOnce()> ::core::ops::Drop for #guard_type<F>
+				{
+					fn drop(&mut self)
+					{
+						if let ::core::option::Option::Some(#hook) = self.0.take()
+						{
+							#hook();
+						}
+					}
+				}
+				#guard_type(::core::option::Option::Some(|| { #code }))
+			};
+		}
+	}
+
+	/// Gets code that disarms a guard created by `get_drop_guard()` so that its code doesn't run when it gets dropped.
+	fn get_guard_disarm(guard_ident: &Ident) -> TokenStream
+	{
+		quote!{ #guard_ident.0 = ::core::option::Option::None; }
+	}
+
+	/// Gets code that creates a guard which runs the cancellation code when it gets dropped, unless it gets disarmed first or gets dropped while unwinding from a panic.
+	/// Futures that get dropped because the code polling them panicked weren't cancelled, so the cancellation code is left to the panic code for them.
+	///
+	/// Inputs:
+	///
+	/// `guard_ident`: Identifier token for the variable that holds the guard.
+	///
+	/// `cancel_code`: The cancellation code.
+	///
+	/// Outputs: The statement that creates the guard.
+	fn get_cancel_guard(guard_ident: &Ident, cancel_code: &TokenStream) -> TokenStream
+	{
+		let cancel_code = quote!
+		{
+			if !::std::thread::panicking()
+			{
+				#cancel_code
+			}
+		};
+		WrappedFn::get_drop_guard(guard_ident, &cancel_code, true)
+	}
+
+	/// Gets the code that arms and disarms the cancellation guard of an `async fn`.
+	///
+	/// Outputs: A tuple of the code that arms the guard and the code that disarms it, or `None`s if there is no cancellation code for an `async fn`.
+	fn get_async_cancel_code(&self) -> (Option<TokenStream>, Option<TokenStream>)
+	{
+		match (&self.cancel_code, self.function.sig.asyncness)
+		{
+			(Some(cancel_code), Some(_)) =>
+			{
+				let guard_ident = Ident::new("cancel_guard", Span::mixed_site());
+				(Some(WrappedFn::get_cancel_guard(&guard_ident, cancel_code)), Some(WrappedFn::get_guard_disarm(&guard_ident)))
+			},
+			_ => (None, None)
+		}
+	}
+
+	/// Gets the original code of the function that gets wrapped.
+	/// If the function has code that runs when it panics, a panic sentinel, or a panic conversion, the original code is run inside of a panic catcher first.
+	/// If the function returns `impl Future` and has cancellation code, the future that the original code returns gets wrapped in another future that runs the cancellation code if it gets dropped early and the post code if it completes.
+	///
+	/// Outputs: The original code, or an error if any of the code that gets added to it doesn't parse.
+	fn get_original_code(&self) -> syn::Result<Block>
+	{
+		let mut og_code = self.function.block.as_ref().clone();
+		let panic_code = self.panic_code.clone().unwrap_or_default();
+		let panic_bindings = self.get_panic_bindings(&panic_code);
+		match (&self.panic_sentinel, &self.panic_conversion)
+		{
+			// Run the panic code and then return the sentinel value if the original code panics
+			(Some(sentinel), _) =>
+			{
+				let sentinel_bindings = self.get_panic_bindings(sentinel);
+				og_code = self.get_panic_catching_code(&og_code, |value| quote!{ #value }, |_| quote!
+				{
+					{
+						#panic_bindings
+						#panic_code
+						#sentinel_bindings
+						#sentinel
+					}
+				})?;
+			},
+			// Run the panic code and then keep unwinding if the original code panics
+			(None, None) if self.panic_code.is_some() =>
+			{
+				og_code = self.get_panic_catching_code(&og_code, |value| quote!{ #value }, |payload_box| quote!
+				{
+					{
+						#panic_bindings
+						#panic_code
+						::std::panic::resume_unwind(#payload_box)
+					}
+				})?;
+			},
+			(None, None) => (),
+			// Run the panic code and then return the converted panic as an error if the original code panics
+			(None, Some(conversion)) =>
+			{
+				let payload_ident = &self.payload_ident;
+				let convert = match conversion
+				{
+					PanicConversion::Payload => quote!{ #payload_ident },
+					PanicConversion::Wrap{ convert, .. } | PanicConversion::Nest{ convert } => convert.clone()
+				};
+				let nested = matches!(conversion, PanicConversion::Nest{..});
+				// The conversion code gets the boxed payload instead of a reference to it
+				let payload_box = payload_box_ident();
+				let fn_name = self.function.sig.ident.to_string();
+				let mut conversion_bindings = bind_if_used(&convert, payload_ident, quote!{ #payload_box });
+				conversion_bindings.extend(bind_if_used(&convert, &self.fn_name_ident, quote!{ #fn_name }));
+				og_code = self.get_panic_catching_code(&og_code, |value| match nested
+				{
+					true => quote!{ #value },
+					false => quote!{ ::core::result::Result::Ok(#value) }
+				}, |_| quote!
+				{
+					{
+						#panic_bindings
+						#panic_code
+						#conversion_bindings
+						::core::result::Result::Err(#convert)
+					}
+				})?;
+			}
+		}
+		// Wrap the returned future so that it runs the cancellation code if it gets dropped early, and the post code once it completes
+		if let (Some(cancel_code), true) = (&self.cancel_code, self.returns_impl_future())
+		{
+			og_code = self.get_cancellable_future_code(&og_code, cancel_code, &self.post_code)?;
+		}
+		Ok(og_code)
+	}
+
+	/// Wraps code that evaluates to a future in another future that runs some code if it gets dropped before it completes, and some other code once it completes.
+	///
+	/// Inputs:
+	///
+	/// `og_code`: The code that evaluates to a future.
+	///
+	/// `cancel_code`: The code that runs if the new future gets dropped early.
+	///
+	/// `post_code`: The code that runs after the future completes, which can use what it evaluated to.
+	///
+	/// Outputs: A block that evaluates to the new future, or an error if the cancellation code or the post code doesn't parse.
+	fn get_cancellable_future_code(&self, og_code: &Block, cancel_code: &TokenStream, post_code: &Option<TokenStream>) -> syn::Result<Block>
+	{
+		let glue_span = self.get_glue_span();
+		let body = Ident::new("body", Span::mixed_site());
+		let future = Ident::new("future", Span::mixed_site());
+		let output = Ident::new("output", Span::mixed_site());
+		let guard_ident = Ident::new("cancel_guard", Span::mixed_site());
+		// Create the guard inside of the new future so that it gets dropped with it
+		let guard = WrappedFn::get_cancel_guard(&guard_ident, cancel_code);
+		let disarm = WrappedFn::get_guard_disarm(&guard_ident);
+		// The post code has to run inside of the new future, since the function returns before the future completes
+		let completion = match post_code
+		{
+			Some(post_code) => get_result_code(&self.get_result_ident(), glue_span, self.get_lint_allows(), quote_spanned!{ glue_span=> #future.await }, TokenStream::new(), false, &quote!{ #disarm #post_code }),
+			None => quote_spanned!
+			{glue_span=>
+				let #output = #future.await;
+				#disarm
+				#output
+			}
+		};
+		// Run the original code in a closure so that early returns still get wrapped
+		let new_code = quote_spanned!
+		{glue_span=>
+			{
+				let #body = || #og_code ;
+				let #future = #body();
+				async move
+				{
+					#guard
+					#completion
+				}
+			}
+		};
+		syn::parse2(new_code)
+	}
+
+	/// Wraps code in a panic catcher that decides what the code evaluates to if it returns normally or panics.
+	/// The code is run in a closure passed to `std::panic::catch_unwind()`, or polled inside of one if the function is async.
+	///
+	/// Inputs:
+	///
+	/// `og_code`: The code that gets run inside of the panic catcher.
+	///
+	/// `ok_value`: Gets the code that the block evaluates to if the code returns normally, given the identifier of the returned value.
+	///
+	/// `panic_value`: Gets the code that the block evaluates to if the code panics, given the identifier of the boxed panic payload.
+	///
+	/// Outputs: A block that runs the code inside of the panic catcher, or an error if the code that decides what it evaluates to doesn't parse.
+	fn get_panic_catching_code(&self, og_code: &Block, ok_value: impl FnOnce(&Ident) -> TokenStream, panic_value: impl FnOnce(&Ident) -> TokenStream) -> syn::Result<Block>
+	{
+		let value = Ident::new("value", Span::mixed_site());
+		let payload_box = payload_box_ident();
+		let ok_value = ok_value(&value);
+		let panic_value = panic_value(&payload_box);
+		let glue_span = self.get_glue_span();
+		// Get what running the code evaluates to, caught in a `Result`
+		let caught = match self.function.sig.asyncness
+		{
+			// Async code has to be polled inside of the panic catcher since it can panic any time it gets polled
+			Some(_) =>
+			{
+				let future = Ident::new("future", Span::mixed_site());
+				let context = Ident::new("context", Span::mixed_site());
+				let poll = Ident::new("poll", Span::mixed_site());
+				quote_spanned!
+				{glue_span=>
+					let mut #future = ::core::pin::pin!(async #og_code);
+					let #value = ::core::future::poll_fn(|#context| match ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| ::core::future::Future::poll(#future.as_mut(), #context)))
+					{
+						::core::result::Result::Ok(#poll) => #poll.map(::core::result::Result::Ok),
+						::core::result::Result::Err(#payload_box) => ::core::task::Poll::Ready(::core::result::Result::Err(#payload_box))
+					}).await;
+				}
+			},
+			None =>
+			{
+				let output = WrappedFn::get_nameable_output(&self.function.sig.output);
+				quote_spanned!
+				{glue_span=>
+					let #value = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| #output #og_code));
+				}
+			}
+		};
+		let new_code = quote_spanned!
+		{glue_span=>
+			{
+				#caught
+				match #value
+				{
+					::core::result::Result::Ok(#value) => #ok_value,
+					::core::result::Result::Err(#payload_box) => #panic_value
+				}
+			}
+		};
+		syn::parse2(new_code)
+	}
+
+	/// Gets the variables that code which runs when the function panics can use, if it uses them.
+	/// The variable named by `payload_ident` holds a reference to the panic payload, and the one named by `fn_name_ident` holds the name of the function.
+	fn get_panic_bindings(&self, code: &TokenStream) -> TokenStream
+	{
+		let payload_box = payload_box_ident();
+		let fn_name = self.function.sig.ident.to_string();
+		let mut bindings = bind_if_used(code, &self.payload_ident, quote!{ &*#payload_box as &(dyn ::core::any::Any + ::core::marker::Send) });
+		bindings.extend(bind_if_used(code, &self.fn_name_ident, quote!{ #fn_name }));
+		bindings
+	}
+
+	/// Joins together pieces of code that get inserted into the same spot in a function, in order.
+	///
+	/// Inputs:
+	///
+	/// `code`: The pieces of code to join. Pieces that are `None` are skipped.
+	///
+	/// Outputs: The joined code, or `None` if every piece was `None`.
+	fn join_code(code: &[&Option<TokenStream>]) -> Option<TokenStream>
+	{
+		code.iter().fold(None, |joined, piece| match (joined, piece)
+		{
+			(Some(mut joined), Some(piece)) =>
+			{
+				joined.extend(piece.clone());
+				Some(joined)
+			},
+			(None, Some(piece)) => Some(piece.clone()),
+			(joined, None) => joined
+		})
+	}
+
+	/// Changes the identifier for the variable that tells finally code whether the function is exiting because of a panic (`panicking` by default).
+	pub fn set_panicking_ident(&mut self, ident: &str)
+	{
+		self.panicking_ident = Ident::new(ident, Span::call_site());
+	}
+
+	/// Changes the identifier for the variable that holds a reference to the panic payload in panic code (`payload` by default).
+	pub fn set_payload_ident(&mut self, ident: &str)
+	{
+		self.payload_ident = Ident::new(ident, Span::call_site());
+	}
+
+	/// Changes the identifier for the variable that holds the name of the function in panic code (`fn_name` by default).
+	pub fn set_fn_name_ident(&mut self, ident: &str)
+	{
+		self.fn_name_ident = Ident::new(ident, Span::call_site());
+	}
+
+	/// Changes the identifier for the variable that holds the location that the function was called from (`caller` by default).
+	/// The identifier shouldn't be used by anything in the original code, since the variable would shadow it.
+	pub fn set_caller_ident(&mut self, ident: &str)
+	{
+		self.caller_ident = Ident::new(ident, Span::call_site());
+	}
+
+	/// Gets the code that creates the guard which runs the finally code of the function when it exits, or `None` if there is no finally code.
+	fn get_finally_guard(&self) -> Option<TokenStream>
+	{
+		self.finally_code.as_ref().map(|finally_code|
+		{
+			// Only tell the finally code whether the function is panicking if it actually checks
+			let mut finally_code_block = bind_if_used(finally_code, &self.panicking_ident, quote!{ ::std::thread::panicking() });
+			finally_code_block.extend(finally_code.clone());
+			let finally_code = finally_code_block;
+			// The guard is never used directly, so its name starts with an underscore to keep it from tripping a lint
+			let guard_ident = Ident::new("_finally_guard", Span::mixed_site());
+			WrappedFn::get_drop_guard(&guard_ident, &finally_code, false)
+		})
+	}
+
+	/// Inserts the unwrapped original code from a function into a function block.
+	///
+	/// Inputs:
+	/// 
+	/// `function_block`: The block of code that goes inside the function where the original code is re-added.
+	///
+	/// `og_code`: The original code from the function.
+	fn add_unwrapped_code(function_block: &mut TokenStream, og_code: &Block)
+	{
+		// Add the statements of the function's old code block after the pre code, without braces since unnecessary braces trip a lint
+		let og_stmts = &og_code.stmts;
+		function_block.extend(quote!{ #(#og_stmts)* });
+	}
+
+	/// Wraps the original code of a function in a closure and inserts code after it inside a function block.
+	/// The closure is moved into a block when it gets called so that it doesn't need a mutable binding to be called as an `FnMut`.
+	///
+	/// Inputs:
+	///
+	/// `function`: The function whose code is being wrapped.
+	///
+	/// `og_code`: The original code of the function that comes before the post code.
+	///
+	/// `output`: The return type of the function, which is also given to the closure.
+	///
+	/// `function_block`: The block of code that goes inside the function where the wrapper code and post code is added.
+	///
+	/// `post_code`: The code to be inserted that runs at the end of the function.
+	fn add_wrapped_post_code(function: &WrappedFn, og_code: &Block, output: &ReturnType, function_block: &mut TokenStream, post_code: &TokenStream)
+	{
+		let wrapper_ident = function.get_wrapper_ident();
+		let glue_span = function.get_glue_span();
+		// Get the return type annotation for the closure
+		let closure_output = WrappedFn::get_nameable_output(output);
+		// Wrap the code in a closure and turn it into a TokenStream
+		let wrapper_code = quote_spanned!
+		{glue_span=>
+			let #wrapper_ident = || #closure_output #og_code ;
+		};
+		// Add the wrapped code that came with the function
+		function_block.extend(wrapper_code);
+		// Call the closure, add the code that runs after it, and return what it returned
+		function.add_result_code(function_block, quote_spanned!{ glue_span=> { #wrapper_ident }() }, TokenStream::new(), output, post_code, true);
+	}
+
+	/// Wraps the original code of an async function in an async block, awaits it, and inserts code after it inside a function block.
+	///
+	/// Inputs:
+	///
+	/// `function`: The function whose code is being wrapped.
+	///
+	/// `og_code`: The original code of the function that comes before the post code.
+	///
+	/// `output`: The return type of the function, which is also given to the variable that holds the awaited return value.
+	///
+	/// `function_block`: The block of code that goes inside the function where the wrapper code and post code is added.
+	///
+	/// `post_code`: The code to be inserted that runs at the end of the function.
+	fn add_wrapped_async_post_code(function: &WrappedFn, og_code: &Block, output: &ReturnType, function_block: &mut TokenStream, post_code: &TokenStream)
+	{
+		let wrapper_ident = function.get_wrapper_ident();
+		let glue_span = function.get_glue_span();
+		// Async blocks can't be given a return type, so the type goes on the variable that the block is awaited into instead
+		let annotation = WrappedFn::get_output_annotation(output);
+		// Wrap the code in an async block and turn it into a TokenStream
+		let wrapper_code = quote_spanned!
+		{glue_span=>
+			let #wrapper_ident = async #og_code ;
+		};
+		// Add the wrapped code that came with the function
+		function_block.extend(wrapper_code);
+		// Await the block in place, add the code that runs after it, and return what it returned
+		function.add_result_code(function_block, quote_spanned!{ glue_span=> #wrapper_ident .await }, annotation, output, post_code, true);
+	}
+
+	/// Puts the original code of a function in a labeled block and inserts code after it inside a function block.
+	/// `return`s (and `?`s where possible) in the original code are rewritten to break out of the block.
+	/// The block only gets a label if something breaks out of it.
+	///
+	/// Inputs:
+	///
+	/// `function`: The function whose code is being wrapped. Its wrapper identifier is used as the label of the block.
+	///
+	/// `og_code`: The original code of the function that comes before the post code.
+	///
+	/// `output`: The return type of the function, which is also given to the variable that holds the return value.
+	///
+	/// `function_block`: The block of code that goes inside the function where the wrapper code and post code is added.
+	///
+	/// `post_code`: The code to be inserted that runs at the end of the function.
+	fn add_labeled_post_code(function: &WrappedFn, og_code: &Block, output: &ReturnType, function_block: &mut TokenStream, post_code: &TokenStream)
+	{
+		// Make the label for the block out of the wrapper identifier
+		let wrapper_ident = function.get_wrapper_ident();
+		let label = Lifetime::new(&format!("'{}", wrapper_ident), wrapper_ident.span());
+		// Rewrite all of the ways that the original code can return from the function so that they break out of the block instead
+		let mut og_code = og_code.clone();
+		let mut rewriter = ReturnRewriter::new(label.clone(), output);
+		rewriter.visit_block_mut(&mut og_code);
+		// Labeled blocks can't be given a type, so the type goes on the variable that holds the result instead
+		let annotation = WrappedFn::get_output_annotation(output);
+		// Unused labels trip a lint, so the block is only labeled if something breaks out of it
+		let labeled_code = match rewriter.breaks
+		{
+			true => quote!{ #label : #og_code },
+			false => quote!{ #og_code }
+		};
+		// Store what the block evaluates to, add the code that runs after it, and return the value
+		// The statement holds the original code, so it doesn't get the function's lint allows
+		function.add_result_code(function_block, labeled_code, annotation, output, post_code, false);
+	}
+
+	/// Stores the value that the original code of a function evaluates to, inserts code after it, and returns the value inside a function block.
+	/// Functions that return `()` only store the value if the post code uses it, since storing `()` in a variable trips lints.
+	///
+	/// Inputs:
+	///
+	/// `function_block`: The block of code that goes inside the function where the code is added.
+	///
+	/// `value`: The code that the value comes from.
+	///
+	/// `annotation`: The type annotation for the variable that holds the value, if it needs one.
+	///
+	/// `output`: The return type of the function.
+	///
+	/// `post_code`: The code to be inserted that runs at the end of the function.
+	///
+	/// `generated`: Whether `value` is only made of generated code, so that the statement can get the function's lint allows.
+	fn add_result_code(&self, function_block: &mut TokenStream, value: TokenStream, annotation: TokenStream, output: &ReturnType, post_code: &TokenStream, generated: bool)
+	{
+		let lint_allows = match generated
+		{
+			true => self.get_lint_allows(),
+			false => TokenStream::new()
+		};
+		function_block.extend(get_result_code(&self.get_result_ident(), self.get_glue_span(), lint_allows, value, annotation, is_unit_output(output), post_code));
+	}
+
+	/// Wraps the original code of a function and inserts code after it inside a function block.
+	/// Uses a labeled block with `ExpansionStrategy::Block`.
+	/// Otherwise uses an async block if the function is an `async fn` and a closure if it isn't.
+	///
+	/// Inputs:
+	///
+	/// `function`: The function whose code is being wrapped.
+	///
+	/// `og_code`: The original code of the function that comes before the post code.
+	///
+	/// `output`: The return type of the wrapped function, which is what the original code evaluates to.
+	///
+	/// `function_block`: The block of code that goes inside the function where the wrapper code and post code is added.
+	///
+	/// `post_code`: The code to be inserted that runs at the end of the function.
+	fn add_post_code(function: &WrappedFn, og_code: &Block, output: &ReturnType, function_block: &mut TokenStream, post_code: &TokenStream)
+	{
+		// Nested functions work the same way in async and non-async functions
+		if function.uses_inner_fn()
+		{
+			WrappedFn::add_inner_fn_post_code(function, og_code, output, function_block, post_code);
+		}
+		// Labeled blocks work the same way in async and non-async functions
+		else if function.get_expansion_strategy() == ExpansionStrategy::Block
+		{
+			WrappedFn::add_labeled_post_code(function, og_code, output, function_block, post_code);
+		}
+		// If the function is async, the original code has to be awaited in place so that `.await` still works inside of it
+		else if function.function.sig.asyncness.is_some()
+		{
+			WrappedFn::add_wrapped_async_post_code(function, og_code, output, function_block, post_code);
+		}
+		else
+		{
+			WrappedFn::add_wrapped_post_code(function, og_code, output, function_block, post_code);
+		}
+	}
+
+	/// Moves the original code of a function into a nested function, calls it, and inserts code after it inside a function block.
+	///
+	/// Inputs:
+	///
+	/// `function`: The function whose code is being wrapped.
+	///
+	/// `og_code`: The original code of the function that comes before the post code.
+	///
+	/// `output`: The return type of the wrapped function, which the nested function also returns.
+	///
+	/// `function_block`: The block of code that goes inside the function where the nested function, the call to it, and post code is added.
+	///
+	/// `post_code`: The code to be inserted that runs at the end of the function.
+	fn add_inner_fn_post_code(function: &WrappedFn, og_code: &Block, output: &ReturnType, function_block: &mut TokenStream, post_code: &TokenStream)
+	{
+		let sig = &function.function.sig;
+		let wrapper_ident = &function.get_wrapper_ident();
+		let glue_span = function.get_glue_span();
+		// The nested function gets the same signature as the wrapped function, except for its name and ABI
+		// Nested functions of `const fn`s are also `const fn`s, so they can still be called
+		let mut inner_sig = sig.clone();
+		inner_sig.ident = wrapper_ident.clone();
+		inner_sig.abi = None;
+		// Unit return types are left out since writing them trips a lint
+		inner_sig.output = match is_unit_output(output)
+		{
+			true => ReturnType::Default,
+			false => output.clone()
+		};
+		// Get the arguments that the wrapped function passes to the nested function
+		let args = function.get_inner_fn_args().into_iter().map(|(_, arg)| arg);
+		let turbofish = WrappedFn::get_turbofish(sig, glue_span);
+		let mut call = quote_spanned!{ glue_span=> #wrapper_ident #turbofish (#(#args),*) };
+		// Async nested functions have to be awaited and unsafe ones have to be called in an unsafe block
+		if sig.asyncness.is_some()
+		{
+			call = quote_spanned!{ glue_span=> #call .await };
+		}
+		if sig.unsafety.is_some()
+		{
+			call = quote_spanned!{ glue_span=> unsafe { #call } };
+		}
+		// Nested functions of `#[track_caller]` functions also track their caller, so the wrapped function's caller gets passed through to the original code
+		let track_caller = match function.has_track_caller()
+		{
+			true => quote!{ #[track_caller] },
+			false => TokenStream::new()
+		};
+		// Declare the nested function
+		let wrapper_code = quote!
+		{
+			#track_caller
+			#inner_sig #og_code
+		};
+		// Add the nested function
+		function_block.extend(wrapper_code);
+		// Call the nested function, add the code that runs after it, and return what it returned
+		function.add_result_code(function_block, call, TokenStream::new(), output, post_code, true);
+	}
+
+	/// Gets the generic arguments that a function passes to a nested copy of itself, as a turbofish.
+	/// Generic types and constants are passed explicitly in case they can't be inferred from the arguments.
+	///
+	/// Inputs:
+	///
+	/// `sig`: The signature of the function.
+	///
+	/// `glue_span`: The span that the turbofish is given.
+	fn get_turbofish(sig: &syn::Signature, glue_span: Span) -> TokenStream
+	{
+		let generic_args: Vec<&Ident> = sig.generics.params.iter().filter_map(|param| match param
+		{
+			GenericParam::Type(param) => Some(&param.ident),
+			GenericParam::Const(param) => Some(&param.ident),
+			GenericParam::Lifetime(_) => None
+		}).collect();
+		match generic_args.is_empty()
+		{
+			true => TokenStream::new(),
+			false => quote_spanned!{ glue_span=> ::<#(#generic_args),*> }
+		}
+	}
+
+	/// Determines whether the original code of the function gets moved into a nested function.
+	/// `ExpansionStrategy::InnerFn` falls back to `ExpansionStrategy::Closure` when the function uses `self`, `Self`, or the generic parameters of its impl block, since nested functions can't use them.
+	fn uses_inner_fn(&self) -> bool
+	{
+		self.get_expansion_strategy() == ExpansionStrategy::InnerFn
+	}
+
+	/// Gets the expansion strategy that actually gets used for the function.
+	/// `ExpansionStrategy::InnerFn` falls back to `ExpansionStrategy::Closure` when the function uses `self` or `Self`.
+	/// `const fn`s can't call closures, so they use `ExpansionStrategy::Block` instead of `ExpansionStrategy::Closure`.
+	/// `#[track_caller]` functions do the same, since closures don't track the caller of the function they're in.
+	fn get_expansion_strategy(&self) -> ExpansionStrategy
+	{
+		let strategy = match self.expansion_strategy
+		{
+			ExpansionStrategy::InnerFn if self.uses_outer_names() => ExpansionStrategy::Closure,
+			strategy => strategy
+		};
+		match strategy
+		{
+			ExpansionStrategy::Closure if self.function.sig.constness.is_some() || self.has_track_caller() => ExpansionStrategy::Block,
+			strategy => strategy
+		}
+	}
+
+	/// Gets all of the code that has been added to the function.
+	fn get_added_code(&self) -> Vec<&TokenStream>
+	{
+		let convert = match &self.panic_conversion
+		{
+			Some(PanicConversion::Wrap{ convert, .. }) | Some(PanicConversion::Nest{ convert }) => Some(convert),
+			_ => None
+		};
+		[
+			&self.pre_code, &self.post_code, &self.async_pre_code, &self.async_post_code,
+			&self.cancel_code, &self.finally_code, &self.panic_code, &self.panic_sentinel
+		].into_iter().flatten().chain(convert).collect()
+	}
+
+	/// Gets an `unsafe fn` that passes its arguments to a nested safe function with all of the code added to it, or `None` if the function isn't an `unsafe fn` or doesn't have any code added to it.
+	/// The body of an `unsafe fn` is an unsafe context in editions before 2024, and so is everything inside of it but nested items, so this is the only place where the added code isn't allowed to do unsafe operations without saying so.
+	/// The original code is put in an `unsafe` block inside of the nested function, so it keeps the unsafe context it already had.
+	///
+	/// Outputs: The `unsafe fn`, or an error if any of the code added to it can't be used in it or doesn't parse.
+	fn get_unsafe_shell(&self) -> Option<syn::Result<ItemFn>>
+	{
+		self.function.sig.unsafety?;
+		let added_code = self.get_added_code();
+		if added_code.is_empty()
+		{
+			return None;
+		}
+		let glue_span = self.get_glue_span();
+		// Wrap a safe copy of the function, with its original code in an `unsafe` block
+		let mut safe_function = self.clone();
+		safe_function.function.sig.unsafety = None;
+		safe_function.function.sig.abi = None;
+		let og_code = &self.function.block;
+		*safe_function.function.block = syn::parse_quote_spanned!{ glue_span=> { unsafe #og_code } };
+		let mut nested_function = match safe_function.try_to_item_fn()
+		{
+			Ok(nested_function) => nested_function,
+			Err(error) => return Some(Err(error))
+		};
+		// Give the nested function a name that nothing in the function or the added code uses
+		let mut all_code = self.function.to_token_stream();
+		all_code.extend(added_code.into_iter().cloned());
+		let nested_ident = Ident::new(&get_fresh_ident_name(&all_code, "hooked"), glue_span);
+		nested_function.sig.ident = nested_ident.clone();
+		nested_function.vis = syn::Visibility::Inherited;
+		// The rest of the attributes are only meant for the `unsafe fn`, and the `unsafe` block doesn't need any unsafe operations in it
+		let track_caller = self.function.attrs.iter().filter(|attr| attr.path().is_ident("track_caller")).cloned();
+		nested_function.attrs = std::iter::once(syn::parse_quote!{ #[allow(unused_unsafe)] }).chain(track_caller).collect();
+		// The `unsafe fn` gets the parameters that can be passed to the nested function and whatever return type it ended up with
+		let mut shell = self.function.clone();
+		let (inputs, args): (Vec<FnArg>, Vec<Expr>) = self.get_inner_fn_args().into_iter().unzip();
+		shell.sig.inputs = inputs.into_iter().collect();
+		shell.sig.output = nested_function.sig.output.clone();
+		let turbofish = WrappedFn::get_turbofish(&self.function.sig, glue_span);
+		let mut call = quote_spanned!{ glue_span=> #nested_ident #turbofish (#(#args),*) };
+		if self.function.sig.asyncness.is_some()
+		{
+			call = quote_spanned!{ glue_span=> #call .await };
+		}
+		*shell.block = syn::parse_quote_spanned!{ glue_span=> { #nested_function #call } };
+		Some(Ok(shell))
+	}
+
+	/// Determines whether the function uses `self`, `Self`, or any of the generic parameters of its impl block.
+	fn uses_outer_names(&self) -> bool
+	{
+		self.uses_outer_names_in(self.function.to_token_stream())
+	}
+
+	/// Determines whether some code uses `self`, `Self`, or any of the generic parameters of the function's impl block.
+	fn uses_outer_names_in(&self, tokens: TokenStream) -> bool
+	{
+		let generic_names: Vec<String> = self.outer_generics.params.iter().map(|param| match param
+		{
+			GenericParam::Type(param) => param.ident.to_string(),
+			GenericParam::Const(param) => param.ident.to_string(),
+			GenericParam::Lifetime(param) => param.lifetime.ident.to_string()
+		}).collect();
+		let mut names = vec!["self", "Self"];
+		names.extend(generic_names.iter().map(String::as_str));
+		tokens_contain_ident(tokens, &names)
+	}
+
+	/// Determines whether the function never returns (`-> !`).
+	fn is_diverging(&self) -> bool
+	{
+		matches!(&self.function.sig.output, ReturnType::Type(_, output) if matches!(**output, Type::Never(_)))
+	}
+
+	/// Determines whether `get_original_code` moves the original code into a closure, for catching panics or for building the future that it returns.
+	fn runs_original_code_in_closure(&self) -> bool
+	{
+		// Async functions poll their original code inside of the panic catcher instead
+		self.function.sig.asyncness.is_none() && (self.catches_panics() || (self.cancel_code.is_some() && self.returns_impl_future()))
+	}
+
+	/// Determines whether `get_original_code` moves the original code of an async function into an async block so that it can be polled inside of a panic catcher.
+	fn runs_original_code_in_async_block(&self) -> bool
+	{
+		self.function.sig.asyncness.is_some() && self.catches_panics()
+	}
+
+	/// Determines whether the function has any code that needs the original code to run inside of a panic catcher.
+	fn catches_panics(&self) -> bool
+	{
+		self.panic_code.is_some() || self.panic_sentinel.is_some() || self.panic_conversion.is_some()
+	}
+
+	/// Determines whether the function has the `#[track_caller]` attribute.
+	fn has_track_caller(&self) -> bool
+	{
+		self.function.attrs.iter().any(|attr| attr.path().is_ident("track_caller"))
+	}
+
+	/// Gets the code that stores the location that the function was called from, or `None` if no code that gets added to the function uses it.
+	/// The location is stored at the start of the function, so code that runs inside of closures or drop guards can still use it.
+	fn get_caller_binding(&self) -> Option<TokenStream>
+	{
+		let conversion_code = match &self.panic_conversion
+		{
+			Some(PanicConversion::Wrap{ convert, .. }) | Some(PanicConversion::Nest{ convert }) => Some(convert.clone()),
+			_ => None
+		};
+		let code = WrappedFn::join_code(&
+		[
+			&self.pre_code, &self.post_code, &self.async_pre_code, &self.async_post_code, &self.cancel_code,
+			&self.finally_code, &self.panic_code, &self.panic_sentinel, &conversion_code
+		])?;
+		let binding = bind_if_used(&code, &self.caller_ident, quote!{ ::core::panic::Location::caller() });
+		match binding.is_empty()
+		{
+			true => None,
+			false => Some(binding)
+		}
+	}
+
+	/// Checks that all of the code being added to the function can actually be used in it.
+	///
+	/// Outputs: An error for each piece of code that can't be used, combined into one, pointing at the code where possible.
+	fn check_code(&self) -> syn::Result<()>
+	{
+		let mut errors: Vec<syn::Error> = Vec::new();
+		// `const fn`s can only run code that works in const contexts
+		if self.function.sig.constness.is_some()
+		{
+			// These hooks all need closures or panic catching
+			let fn_token = self.function.sig.fn_token;
+			let unsupported_hooks = [&self.finally_code, &self.panic_code, &self.panic_sentinel].into_iter().flatten().map(|code| syn::Error::new_spanned(code, ERROR_STRS[6]));
+			errors.extend(unsupported_hooks);
+			if self.panic_conversion.is_some()
+			{
+				errors.push(syn::Error::new(fn_token.span, ERROR_STRS[6]));
+			}
+			// Look for things that can't be used in const contexts in the rest of the hooks
+			for code in [&self.pre_code, &self.post_code].into_iter().flatten()
+			{
+				let mut checker = ConstChecker::default();
+				// Code that doesn't parse as statements can't be checked here
+				if let Ok(block) = syn::parse2::<Block>(quote!{ { #code } })
+				{
+					checker.visit_block(&block);
+				}
+				errors.extend(checker.errors);
+			}
+		}
+		// Functions that never return can't run code after they return or return converted panics
+		if self.is_diverging()
+		{
+			let post_hooks = [&self.post_code, &self.async_post_code].into_iter().flatten().map(|code| syn::Error::new_spanned(code, ERROR_STRS[7]));
+			errors.extend(post_hooks);
+			if self.panic_conversion.is_some()
+			{
+				errors.push(syn::Error::new_spanned(&self.function.sig.output, ERROR_STRS[7]));
+			}
+		}
+		// Code added to an `unsafe fn` runs in a nested function, which can't use anything from the impl block that the function is in
+		if let Some(unsafety) = self.function.sig.unsafety
+		{
+			let added_code = self.get_added_code();
+			let mut all_code = self.function.to_token_stream();
+			all_code.extend(added_code.iter().map(|code| (*code).clone()));
+			if !added_code.is_empty() && self.uses_outer_names_in(all_code)
+			{
+				errors.push(syn::Error::new(unsafety.span, ERROR_STRS[13]));
+			}
+		}
+		// Closures don't track the caller of the function they're in, so the original code would lose it
+		if self.catches_panics() || (self.cancel_code.is_some() && self.returns_impl_future())
+		{
+			let track_caller = self.function.attrs.iter().filter(|attr| attr.path().is_ident("track_caller"));
+			errors.extend(track_caller.map(|attr| syn::Error::new_spanned(attr, ERROR_STRS[12])));
+		}
+		// Labeled blocks can only be broken out of by the `return`s and `?`s that can be rewritten, so anything else would skip the post code
+		// Original code that runs in a closure or an async block inside of the labeled block can already leave it any way it wants
+		let has_post_code = [&self.post_code, &self.async_post_code, &self.cancel_code].into_iter().any(Option::is_some);
+		let in_labeled_block = !self.runs_original_code_in_closure() && !self.runs_original_code_in_async_block();
+		if has_post_code && in_labeled_block && self.get_expansion_strategy() == ExpansionStrategy::Block
+		{
+			let mut checker = ExitChecker::new(ReturnRewriter::get_try_kind(&self.function.sig.output));
+			checker.visit_block(&self.function.block);
+			errors.extend(checker.errors);
+		}
+		// Combine all of the errors into one
+		match errors.into_iter().reduce(|mut combined, error|
+		{
+			combined.combine(error);
+			combined
+		})
+		{
+			Some(error) => Err(error),
+			None => Ok(())
+		}
+	}
+
+	/// Gets the parameters of the wrapped function and the arguments it passes to its nested function when using `ExpansionStrategy::InnerFn`.
+	///
+	/// Outputs: A list of each parameter of the wrapped function paired with the expression that passes it to the nested function.
+	/// Parameters whose patterns can be rebuilt into expressions keep their patterns (without `mut`) and are passed by rebuilding them.
+	/// The rest are renamed so that they can be passed directly.
+	fn get_inner_fn_args(&self) -> Vec<(FnArg, Expr)>
+	{
+		self.function.sig.inputs.iter().enumerate().map(|(index, input)|
+		{
+			let mut input = input.clone();
+			let arg = match &mut input
+			{
+				FnArg::Typed(pat_type) => match pat_to_expr(&pat_type.pat)
+				{
+					// Keep the pattern so that pre-code and post-code can still use its bindings, but it doesn't need to be mutable anymore
+					Some(arg) =>
+					{
+						MutRemover.visit_pat_mut(&mut pat_type.pat);
+						arg
+					},
+					// Replace the pattern with a plain identifier
+					None =>
+					{
+						let ident = Ident::new(&format!("arg{}", index), Span::mixed_site());
+						*pat_type.pat = Pat::Ident(PatIdent { attrs: Vec::new(), by_ref: None, mutability: None, ident: ident.clone(), subpat: None });
+						syn::parse_quote!{ #ident }
+					}
+				},
+				// Receivers are never used with nested functions
+				FnArg::Receiver(receiver) => syn::parse_quote!{ #receiver }
+			};
+			(input, arg)
+		}).collect()
+	}
+
+	/// Gets the function that the wrapped code gets put into.
+	/// With `ExpansionStrategy::InnerFn`, its parameters are changed to be the ones that get passed to the nested function.
+	/// If panics get converted into a new error type, its return type is wrapped in a `Result`.
+	///
+	/// Inputs:
+	///
+	/// `has_post_code`: Whether any code gets run after the original code, which is when the original code gets wrapped.
+	fn get_outer_function(&self, has_post_code: bool) -> ItemFn
+	{
+		let mut outer_function = self.function.clone();
+		if has_post_code && self.uses_inner_fn()
+		{
+			outer_function.sig.inputs = self.get_inner_fn_args().into_iter().map(|(input, _)| input).collect::<Punctuated<FnArg, syn::Token![,]>>();
+		}
+		// Wrap the return type in a `Result` if panics get converted into a new error type
+		// Functions with panic sentinels keep their return type
+		let error_type: Option<Type> = match &self.panic_conversion
+		{
+			_ if self.panic_sentinel.is_some() => None,
+			Some(PanicConversion::Payload) => Some(syn::parse_quote!{ ::std::boxed::Box<dyn ::core::any::Any + ::core::marker::Send + 'static> }),
+			Some(PanicConversion::Wrap{ error_type, .. }) => Some(error_type.as_ref().clone()),
+			Some(PanicConversion::Nest{..}) | None => None
+		};
+		if let Some(error_type) = error_type
+		{
+			outer_function.sig.output = match &self.function.sig.output
+			{
+				ReturnType::Type(arrow, output) => ReturnType::Type(*arrow, syn::parse_quote!{ ::core::result::Result<#output, #error_type> }),
+				ReturnType::Default => syn::parse_quote!{ -> ::core::result::Result<(), #error_type> }
+			};
+		}
+		outer_function
+	}
+
+	/// Gets a version of a function's return type that can be written inside of the function.
+	/// `impl Trait` types can only be written in a function's signature, so they are replaced with `_` and left for the compiler to infer.
+	fn get_nameable_output(output: &ReturnType) -> ReturnType
+	{
+		let mut output = output.clone();
+		ImplTraitEraser.visit_return_type_mut(&mut output);
+		output
+	}
+
+	/// Gets the type annotation for a variable that holds the return value of a function, or nothing if the function doesn't return anything.
+	/// The never type (`!`) can't be written on variables, so it doesn't get an annotation either.
+	fn get_output_annotation(output: &ReturnType) -> TokenStream
+	{
+		match WrappedFn::get_nameable_output(output)
+		{
+			ReturnType::Type(_, output) if !matches!(*output, Type::Never(_)) => quote!{ : #output },
+			_ => TokenStream::new()
+		}
+	}
+
+	/// Gets a `syn::ItemFn` of a function that just had code inserted into it.
+	///
+	/// Inputs:
+	///
+	/// `function`: The original function that is being wrapped.
+	///
+	/// `function_block`: The new block of code that is replacing the old one inside the function that is being wrapped.
+	///
+	/// Outputs: A `syn::ItemFn` of the newly wrapped function, or an error pointing at the tokens that kept the new code from parsing.
+	fn get_wrapped_function(function: &ItemFn, function_block: &TokenStream) -> syn::Result<ItemFn>
+	{
+		// Wrap all of this code inside curly braces
+		let function_block = quote!{ { #function_block } };
+		// Creates a clone of the function
+		let mut new_function = function.clone();
+		// Put the new code block inside the new function
+		new_function.block = syn::parse2(function_block)?;
+		// Return the function with the new code
+		Ok(new_function)
+	}
+}
+
+/// Replaces every `impl Trait` type that it visits with an inferred type (`_`).
+struct ImplTraitEraser;
+
+impl VisitMut for ImplTraitEraser
+{
+	fn visit_type_mut(&mut self, ty: &mut Type)
+	{
+		match ty
+		{
+			Type::ImplTrait(impl_trait) =>
+			{
+				*ty = Type::Infer(TypeInfer { underscore_token: syn::Token![_](impl_trait.impl_token.span) });
+			},
+			_ => visit_mut::visit_type_mut(self, ty)
+		}
+	}
+}
+
+/// Removes `mut` from every identifier pattern that it visits.
+struct MutRemover;
+
+impl VisitMut for MutRemover
+{
+	fn visit_pat_ident_mut(&mut self, pat_ident: &mut PatIdent)
+	{
+		pat_ident.mutability = None;
+		visit_mut::visit_pat_ident_mut(self, pat_ident);
+	}
+}
+
+/// Determines whether a TokenStream contains any of the given identifiers, including inside of groups.
+fn tokens_contain_ident(tokens: TokenStream, idents: &[&str]) -> bool
+{
+	tokens.into_iter().any(|token| match token
+	{
+		TokenTree::Ident(ident) => idents.iter().any(|name| ident == name),
+		TokenTree::Group(group) => tokens_contain_ident(group.stream(), idents),
+		_ => false
+	})
+}
+
+/// Determines whether a TokenStream contains a punctuation character, including inside of groups.
+fn tokens_contain_punct(tokens: TokenStream, punct: char) -> bool
+{
+	tokens.into_iter().any(|token| match token
+	{
+		TokenTree::Punct(token) => token.as_char() == punct,
+		TokenTree::Group(group) => tokens_contain_punct(group.stream(), punct),
+		_ => false
+	})
+}
+
+/// Returns an error pointing at the tokens that keep some code from parsing as statements, if there are any.
+fn check_stmts(code: &TokenStream) -> syn::Result<()>
+{
+	Block::parse_within.parse2(code.clone()).map(|_| ())
+}
+
+/// Moves every token in some code to the location of a span, without changing how names in it resolve.
+///
+/// Inputs:
+///
+/// `tokens`: The code to move.
+///
+/// `span`: The span whose location the tokens get moved to.
+fn locate_tokens(tokens: TokenStream, span: Span) -> TokenStream
+{
+	tokens.into_iter().map(|mut token|
+	{
+		if let TokenTree::Group(group) = &token
+		{
+			let mut new_group = proc_macro2::Group::new(group.delimiter(), locate_tokens(group.stream(), span));
+			new_group.set_span(group.span().located_at(span));
+			token = TokenTree::Group(new_group);
+		}
+		else
+		{
+			token.set_span(token.span().located_at(span));
+		}
+		token
+	}).collect()
+}
+
+/// Determines whether a function's return type is `()`, either by leaving it out or by writing it.
+fn is_unit_output(output: &ReturnType) -> bool
+{
+	match output
+	{
+		ReturnType::Default => true,
+		ReturnType::Type(_, output) => matches!(output.as_ref(), Type::Tuple(tuple) if tuple.elems.is_empty())
+	}
+}
+
+/// Gets code that stores the value that some wrapped code evaluates to, runs code after it, and then evaluates to the value.
+/// The value is only stored if the post code uses it when it's known to be `()`, since storing `()` in a variable trips lints.
+///
+/// Inputs:
+///
+/// `result_ident`: Identifier token for the variable that holds the value.
+///
+/// `glue_span`: The span that the generated code is given.
+///
+/// `lint_allows`: The attribute that allows lints on the statement that stores the value, or nothing.
+///
+/// `value`: The code that the value comes from.
+///
+/// `annotation`: The type annotation for the variable that holds the value, if it needs one.
+///
+/// `unit`: Whether the value is known to be `()`.
+///
+/// `post_code`: The code that runs after the value is stored.
+fn get_result_code(result_ident: &Ident, glue_span: Span, lint_allows: TokenStream, value: TokenStream, annotation: TokenStream, unit: bool, post_code: &TokenStream) -> TokenStream
+{
+	let (binding, return_line) = match unit && !tokens_contain_ident(post_code.clone(), &[&result_ident.to_string()])
+	{
+		true => (quote_spanned!{ glue_span=> () }, TokenStream::new()),
+		false => (quote!{ #result_ident #annotation }, quote!{ #result_ident })
+	};
+	// Store the value, add the code that runs after it, and then evaluate to the value
+	quote_spanned!
+	{glue_span=>
+		#lint_allows let #binding = #value;
+		#post_code
+		#return_line
+	}
+}
+
+/// Gets the attribute that allows some lints, or nothing if there aren't any.
+fn get_lint_allows_attr(lint_allows: &Option<TokenStream>) -> TokenStream
+{
+	match lint_allows
+	{
+		Some(lint_allows) => quote!{ #[allow(#lint_allows)] },
+		None => TokenStream::new()
+	}
+}
+
+/// Gets an identifier token with a `Span::mixed_site()` span if it should be hygienic, or the identifier token unchanged if it shouldn't.
+/// Either way, the identifier token is located at the glue span so that errors about it point there.
+fn get_hygienic_ident(ident: &Ident, hygienic: bool, glue_span: Span) -> Ident
+{
+	let mut ident = ident.clone();
+	if hygienic
+	{
+		ident.set_span(Span::mixed_site());
+	}
+	ident.set_span(ident.span().located_at(glue_span));
+	ident
+}
+
+/// Gets a name for an identifier that doesn't appear anywhere in some code, by adding underscores to the end of a name until it doesn't.
+///
+/// Inputs:
+///
+/// `tokens`: The code that the name can't collide with.
+///
+/// `name`: The name to start with.
+fn get_fresh_ident_name(tokens: &TokenStream, name: &str) -> String
+{
+	let mut name = name.to_string();
+	while tokens_contain_ident(tokens.clone(), &[&name])
+	{
+		name.push('_');
+	}
+	name
+}
+
+/// Gets the identifier token for the variable that holds the boxed payload of a caught panic.
+fn payload_box_ident() -> Ident
+{
+	Ident::new("payload_box", Span::mixed_site())
+}
+
+/// Gets code that declares a variable if some other code uses it, or nothing if it doesn't.
+///
+/// Inputs:
+///
+/// `code`: The code that might use the variable.
+///
+/// `ident`: Identifier token for the variable.
+///
+/// `value`: The value that the variable holds.
+fn bind_if_used(code: &TokenStream, ident: &Ident, value: TokenStream) -> TokenStream
+{
+	match tokens_contain_ident(code.clone(), &[&ident.to_string()])
+	{
+		true => quote!{ let #ident = #value; },
+		false => TokenStream::new()
+	}
+}
+
+/// Rebuilds the value that a pattern destructures out of its bindings.
+///
+/// Outputs: An expression that evaluates to the destructured value, or `None` if the pattern can't be rebuilt (such as if it has `_`, `..`, `ref`, or literals in it).
+fn pat_to_expr(pat: &Pat) -> Option<Expr>
+{
+	match pat
+	{
+		Pat::Ident(pat_ident) if pat_ident.by_ref.is_none() && pat_ident.subpat.is_none() =>
+		{
+			let ident = &pat_ident.ident;
+			Some(syn::parse_quote!{ #ident })
+		},
+		Pat::Paren(pat_paren) => pat_to_expr(&pat_paren.pat),
+		Pat::Tuple(pat_tuple) =>
+		{
+			let elems = pat_tuple.elems.iter().map(pat_to_expr).collect::<Option<Vec<Expr>>>()?;
+			// Tuples with one element need a trailing comma
+			match elems.len()
+			{
+				1 => Some(syn::parse_quote!{ (#(#elems),* ,) }),
+				_ => Some(syn::parse_quote!{ (#(#elems),*) })
+			}
+		},
+		Pat::TupleStruct(pat_tuple_struct) =>
+		{
+			let path = &pat_tuple_struct.path;
+			let elems = pat_tuple_struct.elems.iter().map(pat_to_expr).collect::<Option<Vec<Expr>>>()?;
+			Some(syn::parse_quote!{ #path (#(#elems),*) })
+		},
+		Pat::Struct(pat_struct) if pat_struct.rest.is_none() && pat_struct.qself.is_none() =>
+		{
+			let path = &pat_struct.path;
+			let fields = pat_struct.fields.iter().map(|field|
+			{
+				let member = &field.member;
+				let value = pat_to_expr(&field.pat)?;
+				// Use the shorthand syntax if the field is bound to a variable with the same name
+				match &value
+				{
+					Expr::Path(value) if matches!(member, syn::Member::Named(name) if value.path.is_ident(name)) => Some(quote!{ #member }),
+					_ => Some(quote!{ #member: #value })
+				}
+			}).collect::<Option<Vec<TokenStream>>>()?;
+			Some(syn::parse_quote!{ #path { #(#fields),* } })
+		},
+		_ => None
+	}
+}
+
+/// Finds things in code that can't be used in const contexts.
+/// Items inside of the code are skipped since they aren't part of the const context.
+#[derive(Default)]
+struct ConstChecker
+{
+	/// An error for each thing that was found, pointing at it.
+	errors: Vec<syn::Error>
+}
+
+impl ConstChecker
+{
+	/// Macros from the standard library that can be used in const contexts.
+	const CONST_MACROS: [&'static str; 16] =
+	[
+		"panic", "assert", "debug_assert", "unreachable", "todo", "unimplemented", "matches", "concat",
+		"stringify", "line", "column", "file", "module_path", "env", "option_env", "cfg"
+	];
+}
+
+impl<'ast> Visit<'ast> for ConstChecker
+{
+	fn visit_expr(&mut self, expr: &'ast Expr)
+	{
+		match expr
+		{
+			Expr::Closure(_) | Expr::Async(_) | Expr::Await(_) | Expr::ForLoop(_) | Expr::Try(_) =>
+			{
+				self.errors.push(syn::Error::new_spanned(expr, ERROR_STRS[5]));
+			},
+			_ => visit::visit_expr(self, expr)
+		}
+	}
+
+	fn visit_macro(&mut self, mac: &'ast syn::Macro)
+	{
+		// Only the name of the macro can be checked since its arguments could be anything
+		let is_const = mac.path.segments.last().is_some_and(|segment| ConstChecker::CONST_MACROS.iter().any(|name| segment.ident == name));
+		if !is_const
+		{
+			self.errors.push(syn::Error::new_spanned(mac, ERROR_STRS[5]));
+		}
+	}
+
+	fn visit_item(&mut self, _: &'ast Item)
+	{
+		// Items inside of the code aren't part of the const context
+	}
+}
+
+/// Finds `return`s and `?`s in the original code of a function that `ReturnRewriter` can't rewrite to break out of a labeled block.
+/// Closures, async blocks, and items inside of the code are skipped since `return`s and `?`s inside of them don't return from the function.
+struct ExitChecker
+{
+	/// What kind of type `?`s can be rewritten for.
+	try_kind: TryKind,
+	/// Whether the code being checked is inside of a macro, where nothing can be rewritten.
+	in_macro: bool,
+	/// An error for each `return` or `?` that was found, pointing at it.
+	errors: Vec<syn::Error>
+}
+
+impl ExitChecker
+{
+	/// Creates an `ExitChecker` for code outside of macros.
+	///
+	/// Inputs:
+	///
+	/// `try_kind`: What kind of type `?`s can be rewritten for.
+	fn new(try_kind: TryKind) -> Self
+	{
+		Self { try_kind, in_macro: false, errors: Vec::new() }
+	}
+}
+
+impl<'ast> Visit<'ast> for ExitChecker
+{
+	fn visit_expr(&mut self, expr: &'ast Expr)
+	{
+		match expr
+		{
+			// `return`s and `?`s inside of these don't return from the function
+			Expr::Closure(_) | Expr::Async(_) => (),
+			Expr::Return(return_expr) if self.in_macro =>
+			{
+				self.errors.push(syn::Error::new(return_expr.return_token.span, ERROR_STRS[11]));
+				visit::visit_expr(self, expr);
+			},
+			Expr::Try(try_expr) if self.in_macro || self.try_kind == TryKind::Other =>
+			{
+				self.errors.push(syn::Error::new(try_expr.question_token.span, ERROR_STRS[11]));
+				visit::visit_expr(self, expr);
+			},
+			_ => visit::visit_expr(self, expr)
+		}
+	}
+
+	fn visit_macro(&mut self, mac: &'ast syn::Macro)
+	{
+		// Most macros take comma-separated expressions, which can be checked like the rest of the code
+		if let Ok(args) = mac.parse_body_with(Punctuated::<Expr, Token![,]>::parse_terminated)
+		{
+			let mut checker = ExitChecker { try_kind: self.try_kind, in_macro: true, errors: Vec::new() };
+			args.iter().for_each(|arg| checker.visit_expr(arg));
+			self.errors.extend(checker.errors);
+		}
+		// Anything else could use its tokens in any way
+		else if tokens_contain_ident(mac.tokens.clone(), &["return"]) || tokens_contain_punct(mac.tokens.clone(), '?')
+		{
+			self.errors.push(syn::Error::new_spanned(mac, ERROR_STRS[11]));
+		}
+	}
+
+	fn visit_item(&mut self, _: &'ast Item)
+	{
+		// `return`s inside of nested items don't return from the function
+	}
+}
+
+/// The kinds of types that `?` can be rewritten for when it needs to break out of a labeled block instead of returning from a function.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum TryKind
+{
+	/// The function returns a `Result`, so errors get converted with `From` and broken out with.
+	Result,
+	/// The function returns an `Option`, so `None`s get broken out with.
+	Option,
+	/// The function returns something else, so `?` is left alone.
+	Other
+}
+
+/// Rewrites `return`s and `?`s in the original code of a function so that they break out of a labeled block instead.
+/// Closures, async blocks, and items inside of the code are skipped since `return`s and `?`s inside of them don't return from the function.
+struct ReturnRewriter
+{
+	/// Label of the block that gets broken out of.
+	label: Lifetime,
+	/// What kind of type `?`s can be rewritten for.
+	try_kind: TryKind,
+	/// Whether any `return`s or `?`s have been rewritten into `break`s out of the block.
+	breaks: bool
+}
+
+impl ReturnRewriter
+{
+	/// Creates a `ReturnRewriter` that breaks out of the block with the given label.
+	///
+	/// Inputs:
+	///
+	/// `label`: Label of the block that gets broken out of.
+	///
+	/// `output`: The return type of the function, which determines how `?`s are rewritten.
+	fn new(label: Lifetime, output: &ReturnType) -> Self
+	{
+		Self { label, try_kind: ReturnRewriter::get_try_kind(output), breaks: false }
+	}
+
+	/// Determines what kind of type `?`s can be rewritten for by looking at the last part of a return type's path.
+	fn get_try_kind(output: &ReturnType) -> TryKind
+	{
+		match output
+		{
+			ReturnType::Type(_, output) => match output.as_ref()
+			{
+				Type::Path(path) => match path.path.segments.last()
+				{
+					Some(segment) if segment.ident == "Result" && !matches!(segment.arguments, PathArguments::None) => TryKind::Result,
+					Some(segment) if segment.ident == "Option" && !matches!(segment.arguments, PathArguments::None) => TryKind::Option,
+					_ => TryKind::Other
+				},
+				_ => TryKind::Other
+			},
+			ReturnType::Default => TryKind::Other
+		}
+	}
+}
+
+impl VisitMut for ReturnRewriter
+{
+	fn visit_expr_mut(&mut self, expr: &mut Expr)
+	{
+		let label = self.label.clone();
+		match expr
+		{
+			// `return`s inside of these don't return from the function
+			Expr::Closure(_) | Expr::Async(_) => (),
+			// Turn `return`s into `break`s out of the block
+			Expr::Return(return_expr) =>
+			{
+				self.breaks = true;
+				if let Some(value) = &mut return_expr.expr
+				{
+					self.visit_expr_mut(value);
+				}
+				// The `break` is located at the `return` so that errors about it point there
+				let span = return_expr.return_token.span;
+				*expr = match &return_expr.expr
+				{
+					// Values that start with `::` get parentheses so that they aren't mistaken for a labeled loop (`break 'label: loop {}`)
+					Some(value) if quote!{ #value }.to_string().starts_with(':') => syn::parse_quote_spanned!{ span=> break #label (#value) },
+					value => syn::parse_quote_spanned!{ span=> break #label #value }
+				};
+			},
+			// Turn `?`s into matches that break out of the block on failure
+			Expr::Try(try_expr) if self.try_kind != TryKind::Other =>
+			{
+				self.breaks = true;
+				self.visit_expr_mut(&mut try_expr.expr);
+				let value = &try_expr.expr;
+				// The match is located at the `?` so that errors about converting the error point there
+				// The variables can't collide with anything in the original code since none of it is inside of their scope
+				let span = try_expr.question_token.span;
+				let ok = Ident::new("value", span);
+				let err = Ident::new("error", span);
+				// The values that get broken out with are bound to variables first so that the `break`s don't start with `::`
+				*expr = match self.try_kind
+				{
+					TryKind::Result => syn::parse_quote_spanned!
+					{span=>
+						match #value
+						{
+							::core::result::Result::Ok(#ok) => #ok,
+							::core::result::Result::Err(#err) =>
+							{
+								#[allow(clippy::useless_conversion)]
+								let #err = ::core::result::Result::Err(::core::convert::From::from(#err));
+								break #label #err
+							}
+						}
+					},
+					_ => syn::parse_quote_spanned!
+					{span=>
+						match #value
+						{
+							::core::option::Option::Some(#ok) => #ok,
+							::core::option::Option::None =>
+							{
+								let #err = ::core::option::Option::None;
+								break #label #err
+							}
+						}
+					}
+				};
+			},
+			_ => visit_mut::visit_expr_mut(self, expr)
+		}
+	}
+
+	fn visit_item_mut(&mut self, _: &mut Item)
+	{
+		// `return`s inside of nested items don't return from the function
+	}
+}
+
+/// Main way to construct a `WrappedFn`.
+/// Can be constructed using `syn::parse_macro_input` like this:
+///
+/// ```rust,ignore
+/// let mut function = parse_macro_input!(token_stream as WrappedFn);
+/// ```
+impl Parse for WrappedFn
+{
+	/// Constructs a WrappedFn from a `syn::ParseStream`.
+	fn parse(input: ParseStream) -> syn::Result<Self>
+	{
+		// If no tokens were given to parse, throw an error
+		if input.is_empty()
+		{
+			return Err(syn::Error::new(input.span(), ERROR_STRS[0]))
+		}
+		// Attempt to parse the input tokens as a function
+		// Methods in impl blocks and trait definitions can have things that free functions can't, so they get tried if that fails
+		let tokens: TokenStream = input.parse()?;
+		match syn::parse2::<ItemFn>(tokens.clone())
+		{
+			Ok(function) => Ok(WrappedFn::from(function)),
+			Err(error) =>
+			{
+				if let Ok(function) = syn::parse2::<ImplItemFn>(tokens.clone())
+				{
+					return Ok(WrappedFn::from(function));
+				}
+				if let Ok(function) = syn::parse2::<TraitItemFn>(tokens)
+				{
+					return Ok(WrappedFn::from(function));
+				}
+				// Report the error from parsing the input as a free function since it's the most common kind
+				Err(error)
+			}
+		}
+	}
+}
+
+/// Allows `syn::ItemFn`s that have already been parsed to be wrapped.
+impl From<ItemFn> for WrappedFn
+{
+	/// Constructs a WrappedFn from a free function.
+	fn from(function: ItemFn) -> Self
+	{
+		// Get the return type
+		// let output = match function.sig.output.clone()
+		// {
+		// 	// If a return type was explicitly given, extract it
+		// 	ReturnType::Type(_, o) => WrappedFnOutput::Type(*o),
+		// 	// If no return type was given, use the default return type variant (usually represented as ())
+		// 	ReturnType::Default => WrappedFnOutput::Default
+		// };
+		// Pick identifiers for the wrapper and the result that nothing in the function already uses
+		let function_tokens = function.to_token_stream();
+		let wrapper_ident = Ident::new(&get_fresh_ident_name(&function_tokens, "wrapper"), Span::call_site());
+		let result_ident = Ident::new(&get_fresh_ident_name(&function_tokens, "result"), Span::call_site());
+		let caller_ident = Ident::new(&get_fresh_ident_name(&function_tokens, "caller"), Span::call_site());
+		// Construct a WrappedFn to return
+		Self
+		{
+			function,
+			pre_code: None,
+			post_code: None,
+			async_pre_code: None,
+			async_post_code: None,
+			cancel_code: None,
+			finally_code: None,
+			panic_code: None,
+			panic_conversion: None,
+			panic_sentinel: None,
+			expansion_strategy: ExpansionStrategy::Closure,
+			// output: output,
+			wrapper_ident,
+			result_ident,
+			lint_allows: None,
+			hygienic_idents: false,
+			item_kind: FnItemKind::Fn,
+			outer_generics: syn::Generics::default(),
+			panicking_ident: Ident::new("panicking", Span::call_site()),
+			payload_ident: Ident::new("payload", Span::call_site()),
+			fn_name_ident: Ident::new("fn_name", Span::call_site()),
+			caller_ident
+		}
+	}
+}
+
+/// Allows methods in impl blocks to be wrapped.
+impl From<ImplItemFn> for WrappedFn
+{
+	/// Constructs a WrappedFn from a method in an impl block, which gets converted back into one.
+	fn from(function: ImplItemFn) -> Self
+	{
+		let defaultness = function.defaultness;
+		let mut wrapped_function = WrappedFn::from(ItemFn
+		{
+			attrs: function.attrs,
+			vis: function.vis,
+			sig: function.sig,
+			block: Box::new(function.block)
+		});
+		wrapped_function.item_kind = FnItemKind::ImplFn{ defaultness };
+		wrapped_function
+	}
+}
+
+/// Allows methods in trait definitions to be wrapped.
+impl From<TraitItemFn> for WrappedFn
+{
+	/// Constructs a WrappedFn from a method in a trait definition, which gets converted back into one.
+	/// Methods without a default implementation don't have any code to wrap, so they get converted back unchanged.
+	fn from(function: TraitItemFn) -> Self
+	{
+		let has_default = function.default.is_some();
+		let mut wrapped_function = WrappedFn::from(ItemFn
+		{
+			attrs: function.attrs,
+			vis: syn::Visibility::Inherited,
+			sig: function.sig,
+			block: Box::new(function.default.unwrap_or_else(|| syn::parse_quote!{ {} }))
+		});
+		wrapped_function.item_kind = FnItemKind::TraitFn{ has_default };
+		wrapped_function
+	}
+}
+
+/// Fallible conversions for procedural macros that want to handle errors in the code added to the function themselves.
+/// `TryFrom` can't be used for these since it's already implemented for every type that `From` is.
+impl WrappedFn
+{
+	/// Converts a `WrappedFn` into a `syn::ItemFn`, or returns an error if any of the code added to the function can't be used in it or doesn't parse.
+	/// The error can be turned into a `compile_error!` with `syn::Error::to_compile_error()`.
+	pub fn try_to_item_fn(&self) -> syn::Result<ItemFn>
+	{
+		let function = self;
+		// Make sure that all of the code can be added to the function
+		function.check_code()?;
+		// Code added to an `unsafe fn` shouldn't be able to do unsafe operations just because the original code can
+		if let Some(shell) = function.get_unsafe_shell()
+		{
+			return shell;
+		}
+		// Get the original code of the function along with the guard that runs the cancellation code of an async function
+		let og_code = function.get_original_code()?;
+		let (cancel_arm, cancel_disarm) = function.get_async_cancel_code();
+		// Get the guard that runs the finally code when the function exits
+		let finally_guard = function.get_finally_guard();
+		// Get the location that the function was called from before running anything else
+		let caller_binding = function.get_caller_binding();
+		// The guards and the caller binding are only made of generated code, so they get the function's lint allows
+		let lint_allows = function.get_lint_allows();
+		let [caller_binding, finally_guard, cancel_arm, cancel_disarm] = [caller_binding, finally_guard, cancel_arm, cancel_disarm].map(|code| code.map(|code| quote!{ #lint_allows #code }));
+		// Combine the synchronous and async code that gets run before / after the rest of the function
+		// Async code is nested inside the synchronous code, and the cancellation guard only covers the original code
+		// The finally guard is created before the cancellation guard so that it gets dropped after it
+		let pre_code = WrappedFn::join_code(&[&caller_binding, &function.pre_code, &function.async_pre_code, &finally_guard, &cancel_arm]);
+		// Functions that never return don't need to disarm the cancellation guard since they never finish
+		let post_code = match function.is_diverging()
+		{
+			true => None,
+			false => match function.cancel_code.is_some() && function.returns_impl_future()
+			{
+				// The post code of a function that returns a cancellable future runs inside of the future instead
+				true => None,
+				false => WrappedFn::join_code(&[&cancel_disarm, &function.async_post_code, &function.post_code])
+			}
+		};
+		// Get the function that all of the code gets put into
+		let outer_function = function.get_outer_function(post_code.is_some());
+		// Determine whether the function has code that gets run before / after the rest of the function or not
+		match (&pre_code, &post_code)
+		{
+			// If the function has some code to get run both before and after the function
+			(Some(pre_code), Some(post_code)) =>
+			{
+				// Create a new block of code that will replace the old one in the function and start it with the pre code
+				let mut function_block = pre_code.clone();
+				// Wrap and add the original function code and add the post code to the new function block
+				WrappedFn::add_post_code(function, &og_code, &outer_function.sig.output, &mut function_block, post_code);
+				// Replaces the function's code block with the new one and returns the function
+				WrappedFn::get_wrapped_function(&outer_function, &function_block)
+			},
+			// If the function has some code to get run before the function but not after
+			(Some(pre_code), None) =>
+			{
+				// Create a new block of code that will replace the old one in the function and start it with the pre code
+				let mut function_block = pre_code.clone();
+				// Add the function's original code after the pre code
+				WrappedFn::add_unwrapped_code(&mut function_block, &og_code);
+				// Replaces the function's code block with the new one and returns the function
+				WrappedFn::get_wrapped_function(&outer_function, &function_block)
+			},
+			// If the function has some code to get run after the function but not before
+			(None, Some(post_code)) =>
+			{
+				// Create a new block of code that will replace the old one in the function
+				let mut function_block = TokenStream::new();
+				// Wrap and add the original function code and add the post code to the new function block
+				WrappedFn::add_post_code(function, &og_code, &outer_function.sig.output, &mut function_block, post_code);
+				// Replaces the function's code block with the new one and returns the function
+				WrappedFn::get_wrapped_function(&outer_function, &function_block)
+			},
+			// If the function has no code to insert anywhere
+			(None, None) =>
+			{
+				// Just return the function with its original code
+				let mut new_function = outer_function;
+				new_function.block = Box::new(og_code);
+				Ok(new_function)
+			}
+		}
+	}
+
+	/// Converts a `WrappedFn` into a `proc_macro2::TokenStream` of the same kind of item that it was made from, or returns an error if any of the code added to the function can't be used in it or doesn't parse.
+	pub fn try_to_token_stream(&self) -> syn::Result<TokenStream>
+	{
+		// Convert the function to a `syn::ItemFn` and then convert that into the kind of item that it was made from
+		let function = self.try_to_item_fn()?;
+		Ok(self.get_item_tokens(function))
+	}
+
+	/// Converts a wrapped function into a `proc_macro2::TokenStream` of the kind of item that the `WrappedFn` was made from.
+	fn get_item_tokens(&self, function: ItemFn) -> TokenStream
+	{
+		match self.item_kind
+		{
+			FnItemKind::Fn => function.to_token_stream(),
+			FnItemKind::ImplFn{ .. } => self.get_impl_item_fn(function).to_token_stream(),
+			FnItemKind::TraitFn{ .. } => self.get_trait_item_fn(function).to_token_stream()
+		}
+	}
+
+	/// Converts a wrapped function into a method in an impl block, keeping the `default` keyword if the `WrappedFn` was made from a method that had it.
+	fn get_impl_item_fn(&self, function: ItemFn) -> ImplItemFn
+	{
+		let defaultness = match self.item_kind
+		{
+			FnItemKind::ImplFn{ defaultness } => defaultness,
+			_ => None
+		};
+		ImplItemFn
+		{
+			attrs: function.attrs,
+			vis: function.vis,
+			defaultness,
+			sig: function.sig,
+			block: *function.block
+		}
+	}
+
+	/// Converts a wrapped function into a method in a trait definition.
+	/// If the `WrappedFn` was made from a method without a default implementation, it's converted back unchanged since it has no code to wrap.
+	fn get_trait_item_fn(&self, function: ItemFn) -> TraitItemFn
+	{
+		match self.item_kind
+		{
+			FnItemKind::TraitFn{ has_default: false } => TraitItemFn
+			{
+				attrs: self.function.attrs.clone(),
+				sig: self.function.sig.clone(),
+				default: None,
+				semi_token: Some(Default::default())
+			},
+			_ => TraitItemFn
+			{
+				attrs: function.attrs,
+				sig: function.sig,
+				default: Some(*function.block),
+				semi_token: None
+			}
+		}
+	}
+}
+
+/// Allows `WrappedFn`s to be converted to `syn::ItemFn`s for easier use in procedural macros.
+impl From<&WrappedFn> for ItemFn
+{
+	/// Converts a `WrappedFn` into a `syn::ItemFn`.
+	/// If any of the code added to the function can't be used in it or doesn't parse, the function's code is replaced with the errors so that they get reported when it compiles.
+	fn from(function: &WrappedFn) -> Self
+	{
+		match function.try_to_item_fn()
+		{
+			Ok(new_function) => new_function,
+			Err(error) =>
+			{
+				let errors = error.to_compile_error();
+				let mut new_function = function.function.clone();
+				new_function.block = syn::parse_quote!{ { #errors } };
+				new_function
+			}
+		}
+	}
+}
+
+/// Allows `WrappedFn`s to be converted to `syn::ItemFn`s for easier use in procedural macros.
+impl From<WrappedFn> for ItemFn
+{
+	/// Converts a `WrappedFn` into a `syn::ItemFn`.
+	fn from(function: WrappedFn) -> Self
+	{
+		// Use the `From<&WrappedFn> for ItemFn` implementation
+		Self::from(&function)
+	}
+}
+
+/// Allows `WrappedFn`s to be converted to `syn::ImplItemFn`s for use in procedural macros that wrap methods in impl blocks.
+impl From<&WrappedFn> for ImplItemFn
+{
+	/// Converts a `WrappedFn` into a `syn::ImplItemFn`.
+	fn from(function: &WrappedFn) -> Self
+	{
+		function.get_impl_item_fn(ItemFn::from(function))
+	}
+}
+
+/// Allows `WrappedFn`s to be converted to `syn::ImplItemFn`s for use in procedural macros that wrap methods in impl blocks.
+impl From<WrappedFn> for ImplItemFn
+{
+	/// Converts a `WrappedFn` into a `syn::ImplItemFn`.
+	fn from(function: WrappedFn) -> Self
+	{
+		// Use the `From<&WrappedFn> for ImplItemFn` implementation
+		Self::from(&function)
+	}
+}
+
+/// Allows `WrappedFn`s to be converted to `syn::TraitItemFn`s for use in procedural macros that wrap methods in trait definitions.
+impl From<&WrappedFn> for TraitItemFn
+{
+	/// Converts a `WrappedFn` into a `syn::TraitItemFn`.
+	fn from(function: &WrappedFn) -> Self
+	{
+		function.get_trait_item_fn(ItemFn::from(function))
+	}
+}
+
+/// Allows `WrappedFn`s to be converted to `syn::TraitItemFn`s for use in procedural macros that wrap methods in trait definitions.
+impl From<WrappedFn> for TraitItemFn
+{
+	/// Converts a `WrappedFn` into a `syn::TraitItemFn`.
+	fn from(function: WrappedFn) -> Self
+	{
+		// Use the `From<&WrappedFn> for TraitItemFn` implementation
+		Self::from(&function)
+	}
+}
+
+/// Allows `WrappedFn`s to be converted into `proc_macro2::TokenStream`s for easier use in procedural attribute macros.
+impl From<&WrappedFn> for TokenStream
+{
+	/// Converts a `WrappedFn` into a `proc_macro2::TokenStream` of the same kind of item that it was made from.
+	fn from(function: &WrappedFn) -> Self
+	{
+		// Convert the function to a `syn::ItemFn` and then convert that into the kind of item that it was made from
+		function.get_item_tokens(ItemFn::from(function))
+	}
+}
+
+/// Allows `WrappedFn`s to be converted into `proc_macro2::TokenStream`s for easier use in procedural attribute macros.
+impl From<WrappedFn> for TokenStream
+{
+	/// Converts a `WrappedFn` into a `proc_macro2::TokenStream` of the same kind of item that it was made from.
+	fn from(function: WrappedFn) -> Self
+	{
+		// Use the `From<&WrappedFn> for TokenStream` implementation
+		Self::from(&function)
+	}
+}
+
+/// Allows `WrappedFn`s to be easily appended to and converted to `proc_macro2::TokenStream`s.
+impl ToTokens for WrappedFn
+{
+	/// Adds a `WrappedFn` to the end of a `proc_macro2::TokenStream`.
+	fn to_tokens(&self, tokens: &mut TokenStream)
+	{
+		// Convert the function into a TokenStream
+		let function: TokenStream = self.into();
+		// Add the function to the end of the TokenStream
+		tokens.extend(function);
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use syn::Stmt;
+
+	/// Parses a function into a `WrappedFn`.
+	fn wrapped(function: TokenStream) -> WrappedFn
+	{
+		syn::parse2(function).unwrap()
+	}
+
+	/// Asserts that a `WrappedFn` expands into the expected function.
+	fn assert_expands_to(function: &WrappedFn, expected: TokenStream)
+	{
+		let expected: ItemFn = syn::parse2(expected).unwrap();
+		assert_eq!(ItemFn::from(function), expected);
+	}
+
+	#[test]
+	fn wraps_sync_post_code_in_closure()
+	{
+		let mut function = wrapped(quote!{ fn hello() -> bool { true } });
+		function.set_post_code(quote!{ println!("end"); });
+		let expected = quote!
+		{
+			fn hello() -> bool
+			{
+				let wrapper = || -> bool { true };
+				let result = { wrapper }();
+				println!("end");
+				result
+			}
+		};
+		assert_expands_to(&function, expected);
+	}
+
+	#[test]
+	fn infers_impl_trait_in_closure_return_type()
+	{
+		let mut function = wrapped(quote!{ fn numbers() -> Result<impl Iterator<Item = u8>, Error> { Ok(parse()?.into_iter()) } });
+		function.set_post_code(quote!{ println!("end"); });
+		let expected = quote!
+		{
+			fn numbers() -> Result<impl Iterator<Item = u8>, Error>
+			{
+				let wrapper = || -> Result<_, Error> { Ok(parse()?.into_iter()) };
+				let result = { wrapper }();
+				println!("end");
+				result
+			}
+		};
+		assert_expands_to(&function, expected);
+	}
+
+	#[test]
+	fn wraps_post_code_around_labeled_block()
+	{
+		let mut function = wrapped(quote!
+		{
+			fn first(v: &mut Vec<i32>) -> Option<&mut i32>
+			{
+				if v.is_empty() { return None; }
+				let closure = || { return 1; };
+				Some(&mut v[index(v)?])
+			}
+		});
+		function.set_expansion_strategy(ExpansionStrategy::Block);
+		function.set_post_code(quote!{ println!("end"); });
+		let expected = quote!
+		{
+			fn first(v: &mut Vec<i32>) -> Option<&mut i32>
+			{
+				let result: Option<&mut i32> = 'wrapper:
+				{
+					if v.is_empty() { break 'wrapper None; }
+					let closure = || { return 1; };
+					Some(&mut v[match index(v)
+					{
+						::core::option::Option::Some(value) => value,
+						::core::option::Option::None =>
+						{
+							let error = ::core::option::Option::None;
+							break 'wrapper error
+						}
+					}])
+				};
+				println!("end");
+				result
+			}
+		};
+		assert_expands_to(&function, expected);
+	}
+
+	#[test]
+	fn moves_original_code_into_inner_fn()
+	{
+		let mut function = wrapped(quote!
+		{
+			pub fn sum<'a, T: Copy, const N: usize>(mut total: T, Pair(a, _): Pair<T>, (b, c): (&'a T, u8)) -> T where T: Add<Output = T>
+			{
+				total = total + a + *b;
+				total
+			}
+		});
+		function.set_expansion_strategy(ExpansionStrategy::InnerFn);
+		function.set_post_code(quote!{ println!("end"); });
+		let expected = quote!
+		{
+			pub fn sum<'a, T: Copy, const N: usize>(total: T, arg1: Pair<T>, (b, c): (&'a T, u8)) -> T where T: Add<Output = T>
+			{
+				fn wrapper<'a, T: Copy, const N: usize>(mut total: T, Pair(a, _): Pair<T>, (b, c): (&'a T, u8)) -> T where T: Add<Output = T>
+				{
+					total = total + a + *b;
+					total
+				}
+				let result = wrapper::<T, N>(total, arg1, (b, c));
+				println!("end");
+				result
+			}
+		};
+		assert_expands_to(&function, expected);
+	}
+
+	#[test]
+	fn inner_fn_falls_back_to_closure_for_methods()
+	{
+		let mut function = wrapped(quote!{ fn get(&self) -> u8 { self.value } });
+		function.set_expansion_strategy(ExpansionStrategy::InnerFn);
+		function.set_post_code(quote!{ println!("end"); });
+		let expected = quote!
+		{
+			fn get(&self) -> u8
+			{
+				let wrapper = || -> u8 { self.value };
+				let result = { wrapper }();
+				println!("end");
+				result
+			}
+		};
+		assert_expands_to(&function, expected);
+	}
+
+	#[test]
+	fn wraps_const_functions_without_closures()
+	{
+		let mut function = wrapped(quote!{ const fn double(x: u32) -> u32 { x * 2 } });
+		function.set_pre_code(quote!{ assert!(x < 100); });
+		function.set_post_code(quote!{ assert!(result % 2 == 0); });
+		let expected = quote!
+		{
+			const fn double(x: u32) -> u32
+			{
+				assert!(x < 100);
+				let result: u32 = { x * 2 };
+				assert!(result % 2 == 0);
+				result
+			}
+		};
+		assert_expands_to(&function, expected);
+	}
+
+	#[test]
+	fn reports_non_const_code_in_const_functions()
+	{
+		let mut function = wrapped(quote!{ const fn double(x: u32) -> u32 { x * 2 } });
+		function.set_pre_code(quote!{ println!("start"); for i in 0..x {} });
+		function.set_finally_code(quote!{ cleanup(); });
+		let error = function.check_code().unwrap_err();
+		let messages: Vec<String> = error.into_iter().map(|error| error.to_string()).collect();
+		assert_eq!(messages, [ERROR_STRS[6], ERROR_STRS[5], ERROR_STRS[5]]);
+		// The errors replace the function's code
+		let function = ItemFn::from(&function);
+		assert_eq!(function.block.stmts.len(), 3);
+	}
+
+	#[test]
+	fn keeps_track_caller_functions_closure_free()
+	{
+		let mut function = wrapped(quote!{ #[track_caller] fn checked(x: Option<u32>) -> u32 { x.unwrap() } });
+		function.set_post_code(quote!{ println!("{} called from {}", result, caller); });
+		let expected = quote!
+		{
+			#[track_caller]
+			fn checked(x: Option<u32>) -> u32
+			{
+				let caller = ::core::panic::Location::caller();
+				let result: u32 = { x.unwrap() };
+				println!("{} called from {}", result, caller);
+				result
+			}
+		};
+		assert_expands_to(&function, expected);
+	}
+
+	#[test]
+	fn reports_post_code_in_diverging_functions()
+	{
+		let mut function = wrapped(quote!{ fn exit() -> ! { std::process::exit(0) } });
+		function.set_pre_code(quote!{ println!("exiting"); });
+		function.set_finally_code(quote!{ println!("cleaning up"); });
+		assert!(function.check_code().is_ok());
+		function.set_post_code(quote!{ println!("exited"); });
+		let error = function.check_code().unwrap_err();
+		assert_eq!(error.to_string(), ERROR_STRS[7]);
+	}
+
+	#[test]
+	fn keeps_code_added_to_unsafe_functions_out_of_their_unsafe_context()
+	{
+		let mut function = wrapped(quote!{ #[no_mangle] unsafe extern "C" fn read<T: Copy>(p: *const T, mut n: usize) -> T { n += 1; *p.add(n) } });
+		function.set_pre_code(quote!{ let start = now(); });
+		function.set_post_code(quote!{ log(start); });
+		let expected = quote!
+		{
+			#[no_mangle]
+			unsafe extern "C" fn read<T: Copy>(p: *const T, n: usize) -> T
+			{
+				#[allow(unused_unsafe)]
+				fn hooked<T: Copy>(p: *const T, mut n: usize) -> T
+				{
+					let start = now();
+					let wrapper = || -> T { unsafe { n += 1; *p.add(n) } };
+					let result = { wrapper }();
+					log(start);
+					result
+				}
+				hooked::<T>(p, n)
+			}
+		};
+		assert_expands_to(&function, expected);
+	}
+
+	#[test]
+	fn rejects_code_added_to_unsafe_methods_that_use_self()
+	{
+		let mut function = wrapped(quote!{ unsafe fn get(&self, i: usize) -> u8 { *self.data.get_unchecked(i) } });
+		function.set_pre_code(quote!{ println!("getting"); });
+		let error = function.check_code().unwrap_err();
+		assert_eq!(error.to_string(), ERROR_STRS[13]);
+		// Functions without any added code are left as they are
+		function.remove_pre_code();
+		assert!(function.check_code().is_ok());
+	}
+
+	#[test]
+	fn rejects_exits_that_labeled_blocks_can_not_rewrite()
+	{
+		// `?` on a type that isn't named `Result` or `Option`
+		let mut function = wrapped(quote!{ #[track_caller] fn parse(x: &str) -> MyResult<u32> { let n: u32 = x.parse()?; Ok(n) } });
+		function.set_post_code(quote!{ println!("parsed"); });
+		let error = function.check_code().unwrap_err();
+		assert_eq!(error.to_string(), ERROR_STRS[11]);
+		// `?` inside of a macro, even on an `Option`
+		let mut function = wrapped(quote!{ #[track_caller] fn show(x: Option<u32>) -> Option<u32> { println!("{}", x?); x } });
+		function.set_post_code(quote!{ println!("shown"); });
+		assert!(function.check_code().is_err());
+		// `return`s inside of closures in macros don't leave the block, and functions without post code don't use it
+		let mut function = wrapped(quote!{ #[track_caller] fn check(v: &[u32]) -> MyResult<bool> { assert!(v.iter().all(|x| { return *x > 0; })); Ok(true) } });
+		function.set_post_code(quote!{ println!("checked"); });
+		assert!(function.check_code().is_ok());
+		let mut function = wrapped(quote!{ #[track_caller] fn parse(x: &str) -> MyResult<u32> { let n: u32 = x.parse()?; Ok(n) } });
+		function.set_pre_code(quote!{ println!("parsing"); });
+		assert!(function.check_code().is_ok());
+		// Original code that runs inside of a panic catcher doesn't need its exits rewritten
+		let mut function = wrapped(quote!{ fn parse(x: &str) -> MyResult<u32> { let n: u32 = x.parse()?; Ok(n) } });
+		function.set_expansion_strategy(ExpansionStrategy::Block);
+		function.set_post_code(quote!{ println!("parsed"); });
+		function.set_panic_code(quote!{ println!("panicked"); });
+		assert!(function.check_code().is_ok());
+	}
+
+	#[test]
+	fn rejects_panic_catching_in_track_caller_functions()
+	{
+		let mut function = wrapped(quote!{ #[track_caller] fn checked(x: Option<u32>) -> u32 { x.unwrap() } });
+		function.set_panic_code(quote!{ println!("panicked"); });
+		let error = function.check_code().unwrap_err();
+		assert_eq!(error.to_string(), ERROR_STRS[12]);
+		let mut function = wrapped(quote!{ #[track_caller] fn later(x: Option<u32>) -> impl Future<Output = u32> { let x = x.unwrap(); async move { x } } });
+		function.set_cancel_code(quote!{ println!("cancelled"); }).unwrap();
+		assert!(function.check_code().is_err());
+	}
+
+	#[test]
+	fn keeps_the_caller_binding_out_of_the_original_code()
+	{
+		let mut function = wrapped(quote!{ fn greet(caller: &str) -> String { format!("hi {}", caller) } });
+		assert_eq!(function.caller_ident, "caller_");
+		let location = function.caller_ident.clone();
+		function.set_pre_code(quote!{ println!("{} from {}", caller, #location); });
+		let expected = quote!
+		{
+			fn greet(caller: &str) -> String
+			{
+				let caller_ = ::core::panic::Location::caller();
+				println!("{} from {}", caller, caller_);
+				format!("hi {}", caller)
+			}
+		};
+		assert_expands_to(&function, expected);
+	}
+
+	#[test]
+	fn picks_identifiers_that_do_not_collide()
+	{
+		let mut function = wrapped(quote!{ fn check(result: bool) -> bool { let result_ = !result; result_ } });
+		assert_eq!(function.result_ident, "result__");
+		assert_eq!(function.wrapper_ident, "wrapper");
+		let result = function.get_result_ident();
+		function.set_post_code(quote!{ println!("{} -> {}", result, #result); });
+		let expected = quote!
+		{
+			fn check(result: bool) -> bool
+			{
+				let wrapper = || -> bool { let result_ = !result; result_ };
+				let result__ = { wrapper }();
+				println!("{} -> {}", result, result__);
+				result__
+			}
+		};
+		assert_expands_to(&function, expected);
+	}
+
+	#[test]
+	fn uses_hygienic_identifiers()
+	{
+		let mut function = wrapped(quote!{ fn answer() -> u32 { 42 } });
+		function.set_hygienic_idents(true);
+		let result = function.get_result_ident();
+		function.set_post_code(quote!{ println!("{}", #result); });
+		let function = ItemFn::from(&function);
+		// The identifiers keep their names but not their spans
+		let expected: ItemFn = syn::parse_quote!
+		{
+			fn answer() -> u32
+			{
+				let wrapper = || -> u32 { 42 };
+				let result = { wrapper }();
+				println!("{}", result);
+				result
+			}
+		};
+		assert_eq!(function, expected);
+	}
+
+	#[test]
+	fn generates_lint_free_code_for_unit_functions()
+	{
+		let mut function = wrapped(quote!{ fn hello() -> () { if done() { return; } work(); } });
+		function.set_expansion_strategy(ExpansionStrategy::Block);
+		function.set_lint_allows(quote!{ clippy::pedantic });
+		function.set_finally_code(quote!{ cleanup(); });
+		function.set_post_code(quote!{ println!("done"); });
+		let function = ItemFn::from(&function);
+		let stmts = &function.block.stmts;
+		// The guard only holds generated code, so it gets the allows
+		assert!(matches!(&stmts[0], Stmt::Local(local) if local.attrs.len() == 1));
+		// The result isn't stored since the post code doesn't use it, and the statement holding the original code doesn't get the allows
+		let expected: Stmt = syn::parse_quote!{ let () = 'wrapper: { if done() { break 'wrapper; } work(); }; };
+		assert_eq!(stmts[1], expected);
+		assert_eq!(stmts.len(), 3);
+	}
+
+	#[test]
+	fn keeps_code_when_locating_it_at_a_span()
+	{
+		let mut function = wrapped(quote!{ fn hello() -> bool { true } });
+		let span = function.function.sig.ident.span();
+		function.set_pre_code_spanned(quote!{ check(&[1, 2]); }, span);
+		function.set_post_code_spanned(quote!{ println!("{}", result); }, span);
+		let expected = quote!
+		{
+			fn hello() -> bool
+			{
+				check(&[1, 2]);
+				let wrapper = || -> bool { true };
+				let result = { wrapper }();
+				println!("{}", result);
+				result
+			}
+		};
+		assert_expands_to(&function, expected);
+	}
+
+	#[test]
+	fn returns_errors_for_code_that_does_not_parse()
+	{
+		let mut function = wrapped(quote!{ fn hello() -> bool { true } });
+		assert!(function.try_set_pre_code(quote!{ let x = ; }).is_err());
+		assert!(function.try_set_post_code(quote!{ println!("done"); }).is_ok());
+		// Code that isn't validated when it's set gets reported when the function is converted instead of panicking
+		function.set_pre_code(quote!{ let x = ; });
+		assert!(function.try_to_item_fn().is_err());
+		let function = ItemFn::from(&function);
+		assert!(tokens_contain_ident(function.block.to_token_stream(), &["compile_error"]));
+	}
+
+	#[test]
+	fn wraps_methods_in_impl_blocks_and_traits()
+	{
+		let mut function = wrapped(quote!{ pub(crate) default fn size(&self) -> usize { 0 } });
+		function.set_pre_code(quote!{ check(); });
+		let expected: ImplItemFn = syn::parse_quote!{ pub(crate) default fn size(&self) -> usize { check(); 0 } };
+		assert_eq!(ImplItemFn::from(&function), expected);
+		// Trait methods without a default implementation are left alone
+		let mut function = wrapped(quote!{ fn size(&self) -> usize; });
+		function.set_pre_code(quote!{ check(); });
+		let expected: TraitItemFn = syn::parse_quote!{ fn size(&self) -> usize; };
+		assert_eq!(TraitItemFn::from(&function), expected);
+		assert_eq!(TokenStream::from(&function).to_string(), expected.to_token_stream().to_string());
+	}
+
+	#[test]
+	fn wraps_async_post_code_in_awaited_block()
+	{
+		let mut function = wrapped(quote!{ async fn hello() -> bool { ready().await } });
+		function.set_pre_code(quote!{ println!("start"); });
+		function.set_post_code(quote!{ println!("end"); });
+		let expected = quote!
+		{
+			async fn hello() -> bool
+			{
+				println!("start");
+				let wrapper = async { ready().await };
+				let result: bool = wrapper.await;
+				println!("end");
+				result
+			}
+		};
+		assert_expands_to(&function, expected);
+	}
+
+	#[test]
+	fn nests_async_hooks_inside_sync_hooks()
+	{
+		let mut function = wrapped(quote!{ async fn hello() -> bool { true } });
+		function.set_pre_code(quote!{ println!("start"); });
+		function.set_async_pre_code(quote!{ permit().await; }).unwrap();
+		function.set_async_post_code(quote!{ flush().await; }).unwrap();
+		function.set_post_code(quote!{ println!("end"); });
+		let expected = quote!
+		{
+			async fn hello() -> bool
+			{
+				println!("start");
+				permit().await;
+				let wrapper = async { true };
+				let result: bool = wrapper.await;
+				flush().await;
+				println!("end");
+				result
+			}
+		};
+		assert_expands_to(&function, expected);
+	}
+
+	#[test]
+	fn guards_async_functions_with_cancel_code()
+	{
+		let mut function = wrapped(quote!{ async fn hello() -> bool { true } });
+		function.set_pre_code(quote!{ println!("start"); });
+		function.set_cancel_code(quote!{ println!("cancelled"); }).unwrap();
+		let expected = quote!
+		{
+			async fn hello() -> bool
+			{
+				println!("start");
+				let mut cancel_guard =
+				{
+					struct Guard<F: ::core::ops::FnOnce()>(::core::option::Option<F>);
+					impl<F: ::core::ops::FnOnce()> ::core::ops::Drop for Guard<F>
+					{
+						fn drop(&mut self)
+						{
+							if let ::core::option::Option::Some(hook) = self.0.take()
+							{
+								hook();
+							}
+						}
+					}
+					Guard(::core::option::Option::Some(|| { if !::std::thread::panicking() { println!("cancelled"); } }))
+				};
+				let wrapper = async { true };
+				let result: bool = wrapper.await;
+				cancel_guard.0 = ::core::option::Option::None;
+				result
+			}
+		};
+		assert_expands_to(&function, expected);
+	}
+
+	#[test]
+	fn guards_returned_futures_with_cancel_code()
+	{
+		let mut function = wrapped(quote!{ fn hello() -> impl Future<Output = bool> { async { true } } });
+		function.set_cancel_code(quote!{ println!("cancelled"); }).unwrap();
+		let function = ItemFn::from(&function);
+		// The returned future should be wrapped in a new future that holds the guard
+		let syn::Stmt::Expr(syn::Expr::Async(future), None) = function.block.stmts.last().unwrap()
+		else
+		{
+			panic!("expected the function to return an async block");
+		};
+		assert!(future.capture.is_some());
+		assert_eq!(future.block.stmts.len(), 4);
+	}
+
+	#[test]
+	fn runs_post_code_of_returned_futures_with_cancel_code_when_they_complete()
+	{
+		let mut function = wrapped(quote!{ fn hello() -> impl Future<Output = bool> { async { true } } });
+		function.set_cancel_code(quote!{ println!("cancelled"); }).unwrap();
+		function.set_post_code(quote!{ println!("{}", result); });
+		let function = ItemFn::from(&function);
+		// The post code should only be in the returned future, after it gets awaited and the guard gets disarmed
+		let syn::Stmt::Expr(syn::Expr::Async(future), None) = function.block.stmts.last().unwrap()
+		else
+		{
+			panic!("expected the function to return an async block");
+		};
+		let completion: Vec<String> = future.block.stmts[1..].iter().map(|stmt| quote!{ #stmt }.to_string()).collect();
+		let expected = [quote!{ let result = future.await; }, quote!{ cancel_guard.0 = ::core::option::Option::None; }, quote!{ println!("{}", result); }, quote!{ result }];
+		assert_eq!(completion, expected.map(|stmt| stmt.to_string()));
+	}
+
+	#[test]
+	fn guards_functions_with_finally_code()
+	{
+		let mut function = wrapped(quote!{ fn hello() -> bool { true } });
+		let panicking = &function.panicking_ident;
+		function.set_finally_code(quote!{ println!("{}", #panicking); });
+		let expected = quote!
+		{
+			fn hello() -> bool
+			{
+				let _finally_guard =
+				{
+					struct Guard<F: ::core::ops::FnOnce()>(::core::option::Option<F>);
+					impl<F: ::core::ops::FnOnce()> ::core::ops::Drop for Guard<F>
+					{
+						fn drop(&mut self)
+						{
+							if let ::core::option::Option::Some(hook) = self.0.take()
+							{
+								hook();
+							}
+						}
+					}
+					Guard(::core::option::Option::Some(|| { let panicking = ::std::thread::panicking(); println!("{}", panicking); }))
+				};
+				true
+			}
+		};
+		assert_expands_to(&function, expected);
+	}
+
+	#[test]
+	fn runs_panic_code_before_resuming_panics()
+	{
+		let mut function = wrapped(quote!{ fn hello() -> bool { true } });
+		let fn_name = &function.fn_name_ident;
+		function.set_panic_code(quote!{ println!("{}", #fn_name); });
+		let expected = quote!
+		{
+			fn hello() -> bool
+			{
+				let value = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| -> bool { true }));
+				match value
+				{
+					::core::result::Result::Ok(value) => value,
+					::core::result::Result::Err(payload_box) =>
+					{
+						let fn_name = "hello";
+						println!("{}", fn_name);
+						::std::panic::resume_unwind(payload_box)
+					}
+				}
+			}
+		};
+		assert_expands_to(&function, expected);
+	}
+
+	#[test]
+	fn converts_panics_into_results()
+	{
+		let mut function = wrapped(quote!{ fn hello(name: &str) -> bool { true } });
+		let payload = &function.payload_ident;
+		function.set_panic_conversion(PanicConversion::Wrap{ error_type: syn::parse_quote!{ MyError }, convert: quote!{ MyError::from(#payload) } }).unwrap();
+		let expected = quote!
+		{
+			fn hello(name: &str) -> ::core::result::Result<bool, MyError>
+			{
+				let value = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| -> bool { true }));
+				match value
+				{
+					::core::result::Result::Ok(value) => ::core::result::Result::Ok(value),
+					::core::result::Result::Err(payload_box) =>
+					{
+						let payload = payload_box;
+						::core::result::Result::Err(MyError::from(payload))
+					}
+				}
+			}
+		};
+		assert_expands_to(&function, expected);
+	}
+
+	#[test]
+	fn rejects_nesting_panics_into_non_results()
+	{
+		let mut function = wrapped(quote!{ fn hello() -> bool { true } });
+		let error = function.set_panic_conversion(PanicConversion::Nest{ convert: quote!{ MyError } }).unwrap_err();
+		assert_eq!(error.to_string(), ERROR_STRS[3]);
+		let mut function = wrapped(quote!{ fn hello() -> io::Result<bool> { Ok(true) } });
+		assert!(function.set_panic_conversion(PanicConversion::Nest{ convert: quote!{ MyError } }).is_ok());
+	}
+
+	#[test]
+	fn returns_sentinels_from_extern_functions_that_panic()
+	{
+		let mut function = wrapped(quote!{ extern "C" fn count() -> i32 { 1 } });
+		let fn_name = &function.fn_name_ident;
+		function.set_panic_code(quote!{ record(#fn_name); });
+		function.set_panic_sentinel(quote!{ -1 }).unwrap();
+		let expected = quote!
+		{
+			extern "C" fn count() -> i32
+			{
+				let value = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| -> i32 { 1 }));
+				match value
+				{
+					::core::result::Result::Ok(value) => value,
+					::core::result::Result::Err(payload_box) =>
+					{
+						let fn_name = "count";
+						record(fn_name);
+						-1
+					}
+				}
+			}
+		};
+		assert_expands_to(&function, expected);
+	}
+
+	#[test]
+	fn rejects_panic_sentinels_on_rust_functions()
+	{
+		let mut function = wrapped(quote!{ fn count() -> i32 { 1 } });
+		let error = function.set_panic_sentinel(quote!{ -1 }).unwrap_err();
+		assert_eq!(error.to_string(), ERROR_STRS[4]);
+		let mut function = wrapped(quote!{ extern "Rust" fn count() -> i32 { 1 } });
+		assert!(function.set_panic_sentinel(quote!{ -1 }).is_err());
+		let mut function = wrapped(quote!{ extern fn count() -> i32 { 1 } });
+		assert!(function.set_panic_sentinel(quote!{ -1 }).is_ok());
+	}
+
+	#[test]
+	fn rejects_cancel_code_on_functions_without_futures()
+	{
+		let mut function = wrapped(quote!{ fn hello() -> bool { true } });
+		let error = function.set_cancel_code(quote!{ println!("cancelled"); }).unwrap_err();
+		assert_eq!(error.to_string(), ERROR_STRS[2]);
+	}
 
 	#[test]
-	fn it_works()
+	fn rejects_async_hooks_on_sync_functions()
 	{
-		assert(true);
+		let mut function = wrapped(quote!{ fn hello() -> bool { true } });
+		let error = function.set_async_pre_code(quote!{ permit().await; }).unwrap_err();
+		assert_eq!(error.to_string(), ERROR_STRS[1]);
+		assert!(function.set_async_post_code(quote!{ flush().await; }).is_err());
+		assert!(function.async_pre_code.is_none() && function.async_post_code.is_none());
 	}
 }
@@ -0,0 +1,421 @@
+//! Wraps closures in code that runs before and / or after them, the same way as functions.
+//!
+//! A `WrappedClosure` parses a closure expression (including `move` and `async` closures) and gives it pre-code and post-code.
+//! The closure keeps its attributes, capture mode, parameter patterns, and return type, and only its body gets replaced.
+//!
+//! ```rust,ignore
+//! #[proc_macro]
+//! pub fn traced(item: TokenStream) -> TokenStream
+//! {
+//! 	let mut closure = parse_macro_input!(item as WrappedClosure);
+//! 	let result = closure.get_result_ident();
+//! 	closure.set_pre_code(quote!{ println!("Entering closure"); });
+//! 	closure.set_post_code(quote!{ println!("Leaving closure with {:?}", #result); });
+//! 	proc_macro2::TokenStream::from(closure).into()
+//! }
+//! ```
+//!
+//! A closure like `move |x: i32| x * 2` with both kinds of code turns into this:
+//!
+//! ```rust,ignore
+//! move |x: i32|
+//! {
+//! 	println!("Entering closure");
+//! 	let wrapper = || x * 2;
+//! 	let result = { wrapper }();
+//! 	println!("Leaving closure with {:?}", result);
+//! 	result
+//! }
+//! ```
+//!
+//! The original body is put in its own closure so that `return` and `?` in it still only leave the original body.
+//! The bodies of `async` closures are put in an async block that gets awaited in place instead, so `.await` still works in them.
+//!
+//! Closures whose return type is written as `()` only store the result if the post-code uses it.
+//! Closures with an inferred return type always store it, so lints like `clippy::let_unit_value` can be allowed on the generated statements with `set_lint_allows()` for closures that return `()`.
+
+use proc_macro2::{TokenStream, Span};
+use syn::{ExprClosure, Ident, ReturnType};
+use syn::parse::{Parse, ParseStream};
+use quote::{quote, quote_spanned, ToTokens};
+use crate::{check_stmts, get_fresh_ident_name, get_hygienic_ident, get_lint_allows_attr, get_result_code, is_unit_output};
+
+/// A closure that code can be added to before and / or after it runs.
+#[derive(Clone, Debug)]
+pub struct WrappedClosure
+{
+	/// The closure that gets wrapped.
+	pub closure: ExprClosure,
+	/// Code that runs before the closure's original body. `None` by default.
+	pub pre_code: Option<TokenStream>,
+	/// Code that runs after the closure's original body. `None` by default.
+	pub post_code: Option<TokenStream>,
+	/// Identifier of the closure or async block that holds the closure's original body. `wrapper` by default, with `_`s added until nothing in the closure uses it.
+	pub wrapper_ident: Ident,
+	/// Identifier of the variable that holds the value that the closure's original body returned, for use in the post-code. `result` by default, with `_`s added until nothing in the closure uses it.
+	pub result_ident: Ident,
+	/// Lints that are allowed on the statements that only hold generated code, such as `clippy::let_unit_value`. None by default.
+	/// Statements that hold the closure's original body don't get these, so its own lints stay active.
+	pub lint_allows: Option<TokenStream>,
+	/// Whether the wrapper and result identifiers are hygienic (`Span::mixed_site()`), so that they can't collide with or be seen by anything in the original body. `false` by default.
+	/// The post-code can only use the result variable through the identifier from `get_result_ident` when this is set.
+	pub hygienic_idents: bool
+}
+
+impl WrappedClosure
+{
+	/// Sets the code that gets run before the closure's original body.
+	pub fn set_pre_code(&mut self, pre_code: TokenStream)
+	{
+		self.pre_code = Some(pre_code);
+	}
+
+	/// Sets the code that gets run after the closure's original body.
+	pub fn set_post_code(&mut self, post_code: TokenStream)
+	{
+		self.post_code = Some(post_code);
+	}
+
+	/// Sets the code that gets run before the closure's original body if it parses as statements.
+	///
+	/// Outputs: An error pointing at the tokens that kept the code from parsing, which can be turned into a `compile_error!` with `syn::Error::to_compile_error()`.
+	pub fn try_set_pre_code(&mut self, pre_code: TokenStream) -> syn::Result<()>
+	{
+		check_stmts(&pre_code)?;
+		self.pre_code = Some(pre_code);
+		Ok(())
+	}
+
+	/// Sets the code that gets run after the closure's original body if it parses as statements.
+	///
+	/// Outputs: An error pointing at the tokens that kept the code from parsing, which can be turned into a `compile_error!` with `syn::Error::to_compile_error()`.
+	pub fn try_set_post_code(&mut self, post_code: TokenStream) -> syn::Result<()>
+	{
+		check_stmts(&post_code)?;
+		self.post_code = Some(post_code);
+		Ok(())
+	}
+
+	/// Removes the code that gets run before the closure's original body.
+	pub fn remove_pre_code(&mut self)
+	{
+		self.pre_code = None;
+	}
+
+	/// Removes the code that gets run after the closure's original body.
+	pub fn remove_post_code(&mut self)
+	{
+		self.post_code = None;
+	}
+
+	/// Sets the identifier of the closure or async block that holds the closure's original body.
+	pub fn set_wrapper_ident(&mut self, ident: &str)
+	{
+		self.wrapper_ident = Ident::new(ident, Span::call_site());
+	}
+
+	/// Sets the identifier of the variable that holds the value that the closure's original body returned.
+	pub fn set_result_ident(&mut self, ident: &str)
+	{
+		self.result_ident = Ident::new(ident, Span::call_site());
+	}
+
+	/// Sets the lints that are allowed on the statements that only hold generated code, as a comma-separated list like `clippy::let_unit_value, unused_variables`.
+	/// The closure's original body doesn't get these, so its own lints stay active.
+	pub fn set_lint_allows(&mut self, lint_allows: TokenStream)
+	{
+		self.lint_allows = Some(lint_allows);
+	}
+
+	/// Removes the lints that are allowed on the statements that only hold generated code.
+	pub fn remove_lint_allows(&mut self)
+	{
+		self.lint_allows = None;
+	}
+
+	/// Changes whether the wrapper and result identifiers are hygienic (`Span::mixed_site()`) so that they can't collide with anything in the original body (not hygienic by default).
+	pub fn set_hygienic_idents(&mut self, hygienic: bool)
+	{
+		self.hygienic_idents = hygienic;
+	}
+
+	/// Gets the identifier token for the variable that holds the value that the closure's original body returned, which the post-code can use to get the value.
+	/// This has the span that the variable actually gets declared with, so it still works when the identifiers are hygienic.
+	pub fn get_result_ident(&self) -> Ident
+	{
+		get_hygienic_ident(&self.result_ident, self.hygienic_idents, self.get_glue_span())
+	}
+
+	/// Gets the identifier token for the closure or async block that holds the closure's original body, with the span that it actually gets declared with.
+	fn get_wrapper_ident(&self) -> Ident
+	{
+		get_hygienic_ident(&self.wrapper_ident, self.hygienic_idents, self.get_glue_span())
+	}
+
+	/// Gets the span that the code generated to wrap the closure is given, so that errors in it point at the closure's parameters instead of whatever wrapped it.
+	/// The span resolves names the same way as `Span::call_site()`.
+	fn get_glue_span(&self) -> Span
+	{
+		Span::call_site().located_at(self.closure.or1_token.span)
+	}
+
+	/// Gets the closure's new body, with the pre-code and post-code around the original body.
+	fn get_wrapped_body(&self) -> TokenStream
+	{
+		let body = &self.closure.body;
+		let pre_code = &self.pre_code;
+		let span = self.get_glue_span();
+		// The original body can stay where it is if nothing has to run after it
+		let Some(post_code) = &self.post_code else
+		{
+			return quote_spanned!{ span=> { #pre_code #body } };
+		};
+		let wrapper = self.get_wrapper_ident();
+		let output = &self.closure.output;
+		// Async closures await their original body in place so that it can still use `.await`
+		let (wrapper_code, value) = match self.closure.asyncness
+		{
+			Some(_) => (quote_spanned!{ span=> let #wrapper = async { #body }; }, quote_spanned!{ span=> #wrapper.await }),
+			// Give the inner closure the same return type so that `?` in the original body converts errors the same way
+			None => (quote_spanned!{ span=> let #wrapper = || #output #body; }, quote_spanned!{ span=> { #wrapper }() })
+		};
+		// Inferred return types could be anything, so only a written `()` is known to be unit
+		let unit = matches!(output, ReturnType::Type(..)) && is_unit_output(output);
+		let result_code = get_result_code(&self.get_result_ident(), span, get_lint_allows_attr(&self.lint_allows), value, TokenStream::new(), unit, post_code);
+		quote_spanned!
+		{ span=>
+			{
+				#pre_code
+				#wrapper_code
+				#result_code
+			}
+		}
+	}
+}
+
+/// Main way to construct a `WrappedClosure`.
+/// Can be constructed using `syn::parse_macro_input` like this:
+///
+/// ```rust,ignore
+/// let mut closure = parse_macro_input!(token_stream as WrappedClosure);
+/// ```
+impl Parse for WrappedClosure
+{
+	/// Constructs a WrappedClosure from a `syn::ParseStream`.
+	fn parse(input: ParseStream) -> syn::Result<Self>
+	{
+		let closure: ExprClosure = input.parse()?;
+		Ok(WrappedClosure::from(closure))
+	}
+}
+
+/// Allows `syn::ExprClosure`s that have already been parsed to be wrapped.
+impl From<ExprClosure> for WrappedClosure
+{
+	/// Constructs a WrappedClosure from a closure.
+	fn from(closure: ExprClosure) -> Self
+	{
+		// Pick identifiers for the wrapper and the result that nothing in the closure already uses
+		let closure_tokens = closure.to_token_stream();
+		let wrapper_ident = Ident::new(&get_fresh_ident_name(&closure_tokens, "wrapper"), Span::call_site());
+		let result_ident = Ident::new(&get_fresh_ident_name(&closure_tokens, "result"), Span::call_site());
+		Self
+		{
+			closure,
+			pre_code: None,
+			post_code: None,
+			wrapper_ident,
+			result_ident,
+			lint_allows: None,
+			hygienic_idents: false
+		}
+	}
+}
+
+/// Allows `WrappedClosure`s to be converted to `syn::ExprClosure`s for easier use in procedural macros.
+impl From<&WrappedClosure> for ExprClosure
+{
+	/// Converts a `WrappedClosure` into a `syn::ExprClosure`.
+	fn from(closure: &WrappedClosure) -> Self
+	{
+		let mut new_closure = closure.closure.clone();
+		// Closures without any added code are left as they are
+		if closure.pre_code.is_none() && closure.post_code.is_none()
+		{
+			return new_closure;
+		}
+		// Only the body gets replaced so that the capture mode, parameters, and return type stay the same
+		// Code that doesn't parse is turned into a `compile_error!` so that the error shows up where the closure is
+		*new_closure.body = syn::parse2(closure.get_wrapped_body()).unwrap_or_else(|error|
+		{
+			let error = error.to_compile_error();
+			syn::parse_quote!{ { #error } }
+		});
+		new_closure
+	}
+}
+
+/// Allows `WrappedClosure`s to be converted to `syn::ExprClosure`s for easier use in procedural macros.
+impl From<WrappedClosure> for ExprClosure
+{
+	/// Converts a `WrappedClosure` into a `syn::ExprClosure`.
+	fn from(closure: WrappedClosure) -> Self
+	{
+		// Use the `From<&WrappedClosure> for ExprClosure` implementation
+		Self::from(&closure)
+	}
+}
+
+/// Allows `WrappedClosure`s to be converted into `proc_macro2::TokenStream`s for easier use in procedural macros.
+impl From<&WrappedClosure> for TokenStream
+{
+	/// Converts a `WrappedClosure` into a `proc_macro2::TokenStream`.
+	fn from(closure: &WrappedClosure) -> Self
+	{
+		// Convert the closure to a `syn::ExprClosure` and then convert that into a `TokenStream`
+		let closure = ExprClosure::from(closure);
+		quote!{ #closure }
+	}
+}
+
+/// Allows `WrappedClosure`s to be converted into `proc_macro2::TokenStream`s for easier use in procedural macros.
+impl From<WrappedClosure> for TokenStream
+{
+	/// Converts a `WrappedClosure` into a `proc_macro2::TokenStream`.
+	fn from(closure: WrappedClosure) -> Self
+	{
+		// Use the `From<&WrappedClosure> for TokenStream` implementation
+		Self::from(&closure)
+	}
+}
+
+/// Allows `WrappedClosure`s to be easily appended to and converted to `proc_macro2::TokenStream`s.
+impl ToTokens for WrappedClosure
+{
+	/// Adds a `WrappedClosure` to the end of a `proc_macro2::TokenStream`.
+	fn to_tokens(&self, tokens: &mut TokenStream)
+	{
+		// Convert the closure into a TokenStream
+		let closure: TokenStream = self.into();
+		// Add the closure to the end of the TokenStream
+		tokens.extend(closure);
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	fn assert_wraps_to(closure: &WrappedClosure, expected: TokenStream)
+	{
+		let expected: ExprClosure = syn::parse2(expected).unwrap();
+		assert_eq!(ExprClosure::from(closure), expected);
+	}
+
+	#[test]
+	fn wraps_move_closures()
+	{
+		let mut closure: WrappedClosure = syn::parse_quote!(move |x: i32, (a, b): (u8, u8)| -> i32 { if x < 0 { return 0; } x + a as i32 });
+		closure.set_pre_code(quote!{ start(); });
+		closure.set_post_code(quote!{ end(result); });
+		let expected = quote!
+		{
+			move |x: i32, (a, b): (u8, u8)| -> i32
+			{
+				start();
+				let wrapper = || -> i32 { if x < 0 { return 0; } x + a as i32 };
+				let result = { wrapper }();
+				end(result);
+				result
+			}
+		};
+		assert_wraps_to(&closure, expected);
+	}
+
+	#[test]
+	fn wraps_async_closures_and_avoids_collisions()
+	{
+		let mut closure: WrappedClosure = syn::parse_quote!(async move |result| fetch(result).await);
+		closure.set_post_code(quote!{ end(); });
+		assert_eq!(closure.result_ident, "result_");
+		let expected = quote!
+		{
+			async move |result|
+			{
+				let wrapper = async { fetch(result).await };
+				let result_ = wrapper.await;
+				end();
+				result_
+			}
+		};
+		assert_wraps_to(&closure, expected);
+	}
+
+	#[test]
+	fn only_stores_unit_results_that_are_used()
+	{
+		let mut closure: WrappedClosure = syn::parse_quote!(|| -> () { step(); });
+		closure.set_post_code(quote!{ end(); });
+		closure.set_lint_allows(quote!{ clippy::redundant_closure_call });
+		let expected = quote!
+		{
+			|| -> ()
+			{
+				let wrapper = || -> () { step(); };
+				#[allow(clippy::redundant_closure_call)]
+				let () = { wrapper }();
+				end();
+			}
+		};
+		assert_wraps_to(&closure, expected);
+		// Inferred return types might not be `()`, so the result is always stored
+		let mut closure: WrappedClosure = syn::parse_quote!(|| step());
+		closure.set_post_code(quote!{ end(); });
+		closure.set_wrapper_ident("body");
+		assert_wraps_to(&closure, quote!{ || { let body = || step(); let result = { body }(); end(); result } });
+	}
+
+	#[test]
+	fn uses_hygienic_idents()
+	{
+		let mut closure: WrappedClosure = syn::parse_quote!(|| 5);
+		closure.set_hygienic_idents(true);
+		let result = closure.get_result_ident();
+		closure.set_post_code(quote!{ end(#result); });
+		let ExprClosure{ body, .. } = ExprClosure::from(&closure);
+		let syn::Expr::Block(body) = *body else
+		{
+			panic!("expected the closure's body to be a block");
+		};
+		let syn::Stmt::Local(local) = &body.block.stmts[1] else
+		{
+			panic!("expected the result to be stored in a variable");
+		};
+		let syn::Pat::Ident(binding) = &local.pat else
+		{
+			panic!("expected the result variable to be an identifier");
+		};
+		// The identifier gets the same span that the post-code was given
+		assert_eq!(binding.ident, "result");
+		assert_eq!(format!("{:?}", binding.ident.span()), format!("{:?}", result.span()));
+	}
+
+	#[test]
+	fn turns_code_that_does_not_parse_into_errors()
+	{
+		let mut closure: WrappedClosure = syn::parse_quote!(|x| x + 1);
+		closure.set_post_code(quote!{ let = ; });
+		let tokens = TokenStream::from(&closure).to_string();
+		assert!(tokens.contains("compile_error"));
+		assert!(closure.try_set_pre_code(quote!{ let = ; }).is_err());
+	}
+
+	#[test]
+	fn only_adds_pre_code_in_front()
+	{
+		let mut closure: WrappedClosure = syn::parse_quote!(|x| x + 1);
+		closure.set_pre_code(quote!{ start(); });
+		assert_wraps_to(&closure, quote!{ |x| { start(); x + 1 } });
+	}
+}
@@ -0,0 +1,283 @@
+//! Wraps functions in whole source files from build scripts.
+//!
+//! Some code can't have attributes put on it, such as generated or vendored source files, and some crates can't depend on a procedural macro.
+//! A `WrappedFile` parses a whole Rust source file and turns each function in it into a `WrappedFn`, including the methods in its impl blocks, the default methods in its trait definitions, and everything in its inline modules.
+//! It can then write the woven file to `OUT_DIR` from a build script so that it can be included with `include!`.
+//!
+//! ```rust,ignore
+//! // build.rs
+//! use function_wrapper::WrappedFile;
+//! use quote::quote;
+//!
+//! fn main()
+//! {
+//! 	println!("cargo:rerun-if-changed=src/generated.rs");
+//! 	let mut file = WrappedFile::read("src/generated.rs").unwrap();
+//! 	file.filter.set_pub_only(true);
+//! 	for function in file.get_functions_mut()
+//! 	{
+//! 		let name = function.function.sig.ident.to_string();
+//! 		function.set_pre_code(quote!{ println!("Entering {}", #name); });
+//! 	}
+//! 	file.write_to_out_dir("generated.rs").unwrap();
+//! }
+//!
+//! // src/lib.rs
+//! include!(concat!(env!("OUT_DIR"), "/generated.rs"));
+//! ```
+//!
+//! Since the woven file is written as tokens, errors in the code added to its functions show up as `compile_error!`s wherever it's included.
+//! Files with inner attributes (`#![...]` or `//!`) can't be included with `include!`, so writing them returns an error, and the attributes have to be moved to the file that includes them.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use proc_macro2::TokenStream;
+use syn::{AttrStyle, File};
+use syn::parse::{Parse, ParseStream};
+use quote::{quote, ToTokens};
+use crate::{FnFilter, WrappedFn, ERROR_STRS};
+use crate::wrapped_mod::{get_item_functions, replace_item_functions};
+
+/// A whole Rust source file whose functions can each have code added to them.
+#[derive(Clone, Debug)]
+pub struct WrappedFile
+{
+	/// The source file. Its functions get replaced with the ones in `functions` when it gets converted back into tokens, and everything else is left as it is.
+	/// Functions shouldn't be added to or removed from it, since `functions` has to line up with the functions in it.
+	pub file: File,
+	/// The functions in the file, in the order that they appear in it.
+	/// Methods in impl blocks, methods with default implementations in trait definitions, and functions in inline modules are included where they appear, and methods know about the generic parameters of their impl block or trait (see `WrappedFn::outer_generics`).
+	pub functions: Vec<WrappedFn>,
+	/// Decides which functions `get_functions_mut` gives out. Every function passes by default.
+	pub filter: FnFilter
+}
+
+impl WrappedFile
+{
+	/// Reads and parses a Rust source file.
+	///
+	/// Inputs:
+	///
+	/// `path`: The path to the source file. Relative paths are relative to the crate's root directory when called from a build script.
+	///
+	/// Outputs: The parsed file, or an error if it couldn't be read or isn't valid Rust.
+	pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Self>
+	{
+		let path = path.as_ref();
+		let source = std::fs::read_to_string(path)?;
+		// Say which file couldn't be parsed, since build scripts can read more than one
+		let file = syn::parse_file(&source).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), error)))?;
+		Ok(WrappedFile::from(file))
+	}
+
+	/// Writes the woven file to a directory.
+	///
+	/// Inputs:
+	///
+	/// `dir`: The directory that the file gets written to.
+	///
+	/// `file_name`: The name of the file that gets written, such as `generated.rs`.
+	///
+	/// Outputs: The path of the file that was written, or an error if it has inner attributes or a shebang (which `include!` can't handle) or couldn't be written.
+	pub fn write_to<P: AsRef<Path>>(&self, dir: P, file_name: &str) -> io::Result<PathBuf>
+	{
+		// `//!` comments are parsed as inner attributes, so this catches them too
+		if self.file.shebang.is_some() || self.file.attrs.iter().any(|attr| matches!(attr.style, AttrStyle::Inner(_)))
+		{
+			return Err(io::Error::new(io::ErrorKind::InvalidInput, ERROR_STRS[14]));
+		}
+		let path = dir.as_ref().join(file_name);
+		std::fs::write(&path, TokenStream::from(self).to_string())?;
+		Ok(path)
+	}
+
+	/// Writes the woven file to the `OUT_DIR` of the crate being built so that it can be included with `include!(concat!(env!("OUT_DIR"), "/file_name.rs"))`.
+	/// Can only be used from build scripts since that's the only time `OUT_DIR` is set.
+	///
+	/// Inputs:
+	///
+	/// `file_name`: The name of the file that gets written, such as `generated.rs`.
+	///
+	/// Outputs: The path of the file that was written, or an error if `OUT_DIR` isn't set or the file couldn't be written (see `write_to`).
+	pub fn write_to_out_dir(&self, file_name: &str) -> io::Result<PathBuf>
+	{
+		let out_dir = std::env::var_os("OUT_DIR").ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, ERROR_STRS[10]))?;
+		self.write_to(out_dir, file_name)
+	}
+
+	/// Sets the filter that decides which functions `get_functions_mut` gives out.
+	pub fn set_filter(&mut self, filter: FnFilter)
+	{
+		self.filter = filter;
+	}
+
+	/// Removes the filter so that `get_functions_mut` gives out every function again.
+	pub fn remove_filter(&mut self)
+	{
+		self.filter = FnFilter::default();
+	}
+
+	/// Gets the functions that pass the filter, so that code can be added to them.
+	pub fn get_functions_mut(&mut self) -> impl Iterator<Item = &mut WrappedFn>
+	{
+		let filter = &self.filter;
+		self.functions.iter_mut().filter(move |function| filter.matches(function))
+	}
+
+	/// Gets the functions that pass the filter.
+	pub fn get_functions(&self) -> impl Iterator<Item = &WrappedFn>
+	{
+		self.functions.iter().filter(|function| self.filter.matches(function))
+	}
+}
+
+/// Allows source files to be parsed from tokens.
+impl Parse for WrappedFile
+{
+	/// Constructs a WrappedFile from a `syn::ParseStream`.
+	fn parse(input: ParseStream) -> syn::Result<Self>
+	{
+		let file: File = input.parse()?;
+		Ok(WrappedFile::from(file))
+	}
+}
+
+/// Allows `syn::File`s that have already been parsed to be wrapped.
+impl From<File> for WrappedFile
+{
+	/// Constructs a WrappedFile from a source file.
+	fn from(file: File) -> Self
+	{
+		// Turn each function in the file into a WrappedFn
+		let mut functions = Vec::new();
+		get_item_functions(&file.items, &mut functions);
+		Self
+		{
+			file,
+			functions,
+			filter: FnFilter::default()
+		}
+	}
+}
+
+/// Allows `WrappedFile`s to be converted to `syn::File`s.
+impl From<&WrappedFile> for File
+{
+	/// Converts a `WrappedFile` into a `syn::File`.
+	fn from(file: &WrappedFile) -> Self
+	{
+		// Replace each function with its wrapped counterpart, in order
+		let mut new_file = file.file.clone();
+		replace_item_functions(&mut new_file.items, &mut file.functions.iter(), &file.filter);
+		new_file
+	}
+}
+
+/// Allows `WrappedFile`s to be converted to `syn::File`s.
+impl From<WrappedFile> for File
+{
+	/// Converts a `WrappedFile` into a `syn::File`.
+	fn from(file: WrappedFile) -> Self
+	{
+		// Use the `From<&WrappedFile> for File` implementation
+		Self::from(&file)
+	}
+}
+
+/// Allows `WrappedFile`s to be converted into `proc_macro2::TokenStream`s.
+impl From<&WrappedFile> for TokenStream
+{
+	/// Converts a `WrappedFile` into a `proc_macro2::TokenStream`.
+	fn from(file: &WrappedFile) -> Self
+	{
+		// Convert the file to a `syn::File` and then convert that into a `TokenStream`
+		let file = File::from(file);
+		quote!{ #file }
+	}
+}
+
+/// Allows `WrappedFile`s to be converted into `proc_macro2::TokenStream`s.
+impl From<WrappedFile> for TokenStream
+{
+	/// Converts a `WrappedFile` into a `proc_macro2::TokenStream`.
+	fn from(file: WrappedFile) -> Self
+	{
+		// Use the `From<&WrappedFile> for TokenStream` implementation
+		Self::from(&file)
+	}
+}
+
+/// Allows `WrappedFile`s to be easily appended to and converted to `proc_macro2::TokenStream`s.
+impl ToTokens for WrappedFile
+{
+	/// Adds a `WrappedFile` to the end of a `proc_macro2::TokenStream`.
+	fn to_tokens(&self, tokens: &mut TokenStream)
+	{
+		// Convert the file into a TokenStream
+		let file: TokenStream = self.into();
+		// Add the file to the end of the TokenStream
+		tokens.extend(file);
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	#[test]
+	fn weaves_files()
+	{
+		let source = "
+			use std::fmt;
+			pub fn handle_get() -> u8 { 1 }
+			pub mod nested { pub fn handle_post() -> u8 { 2 } }
+		";
+		let mut file = WrappedFile::from(syn::parse_file(source).unwrap());
+		for function in file.get_functions_mut()
+		{
+			function.set_pre_code(quote!{ trace(); });
+		}
+		// Write the file somewhere that won't collide with other test runs and read it back
+		let dir = std::env::temp_dir().join(format!("function-wrapper-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let path = file.write_to(&dir, "generated.rs").unwrap();
+		let woven = WrappedFile::read(&path).unwrap();
+		std::fs::remove_dir_all(&dir).unwrap();
+		let expected: File = syn::parse_quote!
+		{
+			use std::fmt;
+			pub fn handle_get() -> u8 { trace(); 1 }
+			pub mod nested { pub fn handle_post() -> u8 { trace(); 2 } }
+		};
+		assert_eq!(woven.file, expected);
+	}
+
+	#[test]
+	fn rejects_files_that_can_not_be_included()
+	{
+		let dir = std::env::temp_dir().join(format!("function-wrapper-inner-{}", std::process::id()));
+		for source in ["#![allow(dead_code)] pub fn handle_get() -> u8 { 1 }", "//! Handlers.\npub fn handle_get() -> u8 { 1 }"]
+		{
+			let file = WrappedFile::from(syn::parse_file(source).unwrap());
+			let error = file.write_to(&dir, "generated.rs").unwrap_err();
+			assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+			assert_eq!(error.to_string(), ERROR_STRS[14]);
+		}
+		// Nothing should have been written
+		assert!(!dir.exists());
+	}
+
+	#[test]
+	fn says_which_file_did_not_parse()
+	{
+		let dir = std::env::temp_dir().join(format!("function-wrapper-invalid-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let path = dir.join("invalid.rs");
+		std::fs::write(&path, "fn {").unwrap();
+		let error = WrappedFile::read(&path).unwrap_err();
+		std::fs::remove_dir_all(&dir).unwrap();
+		assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+		assert!(error.to_string().starts_with(&path.display().to_string()));
+	}
+}
@@ -0,0 +1,267 @@
+//! Wraps every method in an impl block with one attribute.
+//!
+//! A `WrappedImpl` parses an impl block and turns each of its methods into a `WrappedFn`, so code can be added to all of them at once.
+//! Its `FnFilter` decides which methods `get_methods_mut` gives out to have code added to them.
+//!
+//! ```rust,ignore
+//! #[proc_macro_attribute]
+//! pub fn trace_all(_attr: TokenStream, item: TokenStream) -> TokenStream
+//! {
+//! 	let mut item_impl = parse_macro_input!(item as WrappedImpl);
+//! 	item_impl.filter.set_pub_only(true);
+//! 	item_impl.filter.set_skip_attr("no_trace");
+//! 	// The type that the impl block is for can be used in the code added to each method.
+//! 	let self_ty = item_impl.item_impl.self_ty.clone();
+//! 	for method in item_impl.get_methods_mut()
+//! 	{
+//! 		let name = method.function.sig.ident.to_string();
+//! 		method.set_pre_code(quote!{ println!("Entering {}::{}", stringify!(#self_ty), #name); });
+//! 	}
+//! 	proc_macro2::TokenStream::from(item_impl).into()
+//! }
+//! ```
+
+use proc_macro2::TokenStream;
+use syn::{ItemImpl, ImplItem, ImplItemFn};
+use syn::parse::{Parse, ParseStream};
+use quote::{quote, ToTokens};
+use crate::{FnFilter, WrappedFn};
+
+/// An impl block whose methods can each have code added to them.
+#[derive(Clone, Debug)]
+pub struct WrappedImpl
+{
+	/// The impl block. Its generic parameters and the type that it's for can be used in the code added to the methods.
+	/// Its methods get replaced with the ones in `methods` when it gets converted back into tokens.
+	pub item_impl: ItemImpl,
+	/// The methods in the impl block, in the order that they appear in it.
+	/// Each one knows about the generic parameters of the impl block (see `WrappedFn::outer_generics`).
+	pub methods: Vec<WrappedFn>,
+	/// Decides which methods `get_methods_mut` gives out. Every method passes by default.
+	pub filter: FnFilter
+}
+
+impl WrappedImpl
+{
+	/// Sets the filter that decides which methods `get_methods_mut` gives out.
+	pub fn set_filter(&mut self, filter: FnFilter)
+	{
+		self.filter = filter;
+	}
+
+	/// Removes the filter so that `get_methods_mut` gives out every method again.
+	pub fn remove_filter(&mut self)
+	{
+		self.filter = FnFilter::default();
+	}
+
+	/// Gets the methods that pass the filter, so that code can be added to them.
+	pub fn get_methods_mut(&mut self) -> impl Iterator<Item = &mut WrappedFn>
+	{
+		let filter = &self.filter;
+		self.methods.iter_mut().filter(move |method| filter.matches(method))
+	}
+
+	/// Gets the methods that pass the filter.
+	pub fn get_methods(&self) -> impl Iterator<Item = &WrappedFn>
+	{
+		self.methods.iter().filter(|method| self.filter.matches(method))
+	}
+}
+
+/// Main way to construct a `WrappedImpl`.
+/// Can be constructed using `syn::parse_macro_input` like this:
+///
+/// ```rust,ignore
+/// let mut item_impl = parse_macro_input!(token_stream as WrappedImpl);
+/// ```
+impl Parse for WrappedImpl
+{
+	/// Constructs a WrappedImpl from a `syn::ParseStream`.
+	fn parse(input: ParseStream) -> syn::Result<Self>
+	{
+		let item_impl: ItemImpl = input.parse()?;
+		Ok(WrappedImpl::from(item_impl))
+	}
+}
+
+/// Allows `syn::ItemImpl`s that have already been parsed to be wrapped.
+impl From<ItemImpl> for WrappedImpl
+{
+	/// Constructs a WrappedImpl from an impl block.
+	fn from(item_impl: ItemImpl) -> Self
+	{
+		// Turn each method into a WrappedFn that knows about the generics of the impl block
+		let methods = get_impl_methods(&item_impl).collect();
+		Self
+		{
+			item_impl,
+			methods,
+			filter: FnFilter::default()
+		}
+	}
+}
+
+/// Allows `WrappedImpl`s to be converted to `syn::ItemImpl`s for easier use in procedural macros.
+impl From<&WrappedImpl> for ItemImpl
+{
+	/// Converts a `WrappedImpl` into a `syn::ItemImpl`.
+	fn from(item_impl: &WrappedImpl) -> Self
+	{
+		// Replace each method with its wrapped counterpart, in order
+		let mut new_impl = item_impl.item_impl.clone();
+		replace_impl_methods(&mut new_impl, &mut item_impl.methods.iter(), &item_impl.filter);
+		new_impl
+	}
+}
+
+/// Allows `WrappedImpl`s to be converted to `syn::ItemImpl`s for easier use in procedural macros.
+impl From<WrappedImpl> for ItemImpl
+{
+	/// Converts a `WrappedImpl` into a `syn::ItemImpl`.
+	fn from(item_impl: WrappedImpl) -> Self
+	{
+		// Use the `From<&WrappedImpl> for ItemImpl` implementation
+		Self::from(&item_impl)
+	}
+}
+
+/// Allows `WrappedImpl`s to be converted into `proc_macro2::TokenStream`s for easier use in procedural attribute macros.
+impl From<&WrappedImpl> for TokenStream
+{
+	/// Converts a `WrappedImpl` into a `proc_macro2::TokenStream`.
+	fn from(item_impl: &WrappedImpl) -> Self
+	{
+		// Convert the impl block to a `syn::ItemImpl` and then convert that into a `TokenStream`
+		let item_impl = ItemImpl::from(item_impl);
+		quote!{ #item_impl }
+	}
+}
+
+/// Allows `WrappedImpl`s to be converted into `proc_macro2::TokenStream`s for easier use in procedural attribute macros.
+impl From<WrappedImpl> for TokenStream
+{
+	/// Converts a `WrappedImpl` into a `proc_macro2::TokenStream`.
+	fn from(item_impl: WrappedImpl) -> Self
+	{
+		// Use the `From<&WrappedImpl> for TokenStream` implementation
+		Self::from(&item_impl)
+	}
+}
+
+/// Allows `WrappedImpl`s to be easily appended to and converted to `proc_macro2::TokenStream`s.
+impl ToTokens for WrappedImpl
+{
+	/// Adds a `WrappedImpl` to the end of a `proc_macro2::TokenStream`.
+	fn to_tokens(&self, tokens: &mut TokenStream)
+	{
+		// Convert the impl block into a TokenStream
+		let item_impl: TokenStream = self.into();
+		// Add the impl block to the end of the TokenStream
+		tokens.extend(item_impl);
+	}
+}
+
+/// Turns each method in an impl block into a `WrappedFn` that knows about the generic parameters of the impl block.
+///
+/// Inputs:
+///
+/// `item_impl`: The impl block.
+///
+/// Outputs: The methods in the order that they appear in the impl block.
+pub(crate) fn get_impl_methods(item_impl: &ItemImpl) -> impl Iterator<Item = WrappedFn> + '_
+{
+	item_impl.items.iter().filter_map(|item| match item
+	{
+		ImplItem::Fn(method) =>
+		{
+			let mut method = WrappedFn::from(method.clone());
+			method.set_outer_generics(item_impl.generics.clone());
+			Some(method)
+		},
+		_ => None
+	})
+}
+
+/// Replaces each method in an impl block with its wrapped counterpart, in order, and removes the filter's skip attribute from all of them.
+///
+/// Inputs:
+///
+/// `item_impl`: The impl block that gets its methods replaced.
+///
+/// `methods`: The wrapped methods, in the order that they appear in the impl block. Only one is taken for each method in the impl block.
+///
+/// `filter`: The filter whose skip attribute gets removed.
+pub(crate) fn replace_impl_methods<'a>(item_impl: &mut ItemImpl, methods: &mut impl Iterator<Item = &'a WrappedFn>, filter: &FnFilter)
+{
+	for item in &mut item_impl.items
+	{
+		if let ImplItem::Fn(method) = item
+		{
+			if let Some(wrapped_method) = methods.next()
+			{
+				*method = ImplItemFn::from(wrapped_method);
+			}
+			// The skip attribute isn't a real attribute, so it can't be left on the method
+			filter.remove_skip_attrs(&mut method.attrs);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use crate::ExpansionStrategy;
+
+	#[test]
+	fn wraps_filtered_methods()
+	{
+		let mut item_impl: WrappedImpl = syn::parse_quote!
+		{
+			impl<T: Clone> Stack<T>
+			{
+				const LIMIT: usize = 8;
+				pub fn peek(&self) -> Option<T> { self.items.last().cloned() }
+				#[no_wrap]
+				pub fn len(&self) -> usize { self.items.len() }
+				fn clear(&mut self) { self.items.clear() }
+			}
+		};
+		item_impl.filter.set_pub_only(true);
+		item_impl.filter.set_skip_attr("no_wrap");
+		for method in item_impl.get_methods_mut()
+		{
+			method.set_pre_code(quote!{ trace(); });
+		}
+		let expected: ItemImpl = syn::parse_quote!
+		{
+			impl<T: Clone> Stack<T>
+			{
+				const LIMIT: usize = 8;
+				pub fn peek(&self) -> Option<T> { trace(); self.items.last().cloned() }
+				pub fn len(&self) -> usize { self.items.len() }
+				fn clear(&mut self) { self.items.clear() }
+			}
+		};
+		assert_eq!(ItemImpl::from(&item_impl), expected);
+	}
+
+	#[test]
+	fn keeps_impl_generics_out_of_inner_fns()
+	{
+		let mut item_impl: WrappedImpl = syn::parse_quote!
+		{
+			impl<T: Default> Maker<T>
+			{
+				fn make() -> T { T::default() }
+			}
+		};
+		let method = &mut item_impl.methods[0];
+		method.set_expansion_strategy(ExpansionStrategy::InnerFn);
+		method.set_post_code(quote!{ done(); });
+		// The nested function couldn't use `T`, so a closure is used instead
+		let tokens = TokenStream::from(&item_impl).to_string();
+		assert!(tokens.contains("let wrapper = | |"));
+	}
+}
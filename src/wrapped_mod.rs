@@ -0,0 +1,383 @@
+//! Wraps every function in an inline module with one attribute.
+//!
+//! A `WrappedMod` parses a module and turns each function in it into a `WrappedFn`, including the methods in its impl blocks, the default methods in its trait definitions, and everything in the modules nested inside of it.
+//! Its `FnFilter` decides which functions `get_functions_mut` gives out to have code added to them.
+//!
+//! ```rust,ignore
+//! #[proc_macro_attribute]
+//! pub fn trace_mod(_attr: TokenStream, item: TokenStream) -> TokenStream
+//! {
+//! 	let mut item_mod = parse_macro_input!(item as WrappedMod);
+//! 	item_mod.filter.add_name_pattern("handle_*");
+//! 	for function in item_mod.get_functions_mut()
+//! 	{
+//! 		let name = function.function.sig.ident.to_string();
+//! 		function.set_pre_code(quote!{ println!("Entering {}", #name); });
+//! 	}
+//! 	proc_macro2::TokenStream::from(item_mod).into()
+//! }
+//! ```
+
+use proc_macro2::TokenStream;
+use syn::{Item, ItemFn, ItemMod, ItemTrait, TraitItem, TraitItemFn};
+use syn::parse::{Parse, ParseStream};
+use quote::{quote, ToTokens};
+use crate::{FnFilter, WrappedFn, ERROR_STRS};
+use crate::wrapped_impl::{get_impl_methods, replace_impl_methods};
+
+/// An inline module whose functions can each have code added to them.
+#[derive(Clone, Debug)]
+pub struct WrappedMod
+{
+	/// The module. Its functions get replaced with the ones in `functions` when it gets converted back into tokens, and everything else is left as it is.
+	/// Functions shouldn't be added to or removed from it, since `functions` has to line up with the functions in it.
+	pub item_mod: ItemMod,
+	/// The functions in the module, in the order that they appear in it.
+	/// Methods in impl blocks, methods with default implementations in trait definitions, and functions in nested inline modules are included where they appear, and methods know about the generic parameters of their impl block or trait (see `WrappedFn::outer_generics`).
+	/// Trait methods without a default implementation aren't included since they don't have any code to wrap.
+	/// Functions inside of other functions aren't included since they get wrapped along with the function that they're in.
+	pub functions: Vec<WrappedFn>,
+	/// Decides which functions `get_functions_mut` gives out. Every function passes by default.
+	pub filter: FnFilter
+}
+
+impl WrappedMod
+{
+	/// Sets the filter that decides which functions `get_functions_mut` gives out.
+	pub fn set_filter(&mut self, filter: FnFilter)
+	{
+		self.filter = filter;
+	}
+
+	/// Removes the filter so that `get_functions_mut` gives out every function again.
+	pub fn remove_filter(&mut self)
+	{
+		self.filter = FnFilter::default();
+	}
+
+	/// Gets the functions that pass the filter, so that code can be added to them.
+	pub fn get_functions_mut(&mut self) -> impl Iterator<Item = &mut WrappedFn>
+	{
+		let filter = &self.filter;
+		self.functions.iter_mut().filter(move |function| filter.matches(function))
+	}
+
+	/// Gets the functions that pass the filter.
+	pub fn get_functions(&self) -> impl Iterator<Item = &WrappedFn>
+	{
+		self.functions.iter().filter(|function| self.filter.matches(function))
+	}
+}
+
+/// Main way to construct a `WrappedMod`.
+/// Can be constructed using `syn::parse_macro_input` like this:
+///
+/// ```rust,ignore
+/// let mut item_mod = parse_macro_input!(token_stream as WrappedMod);
+/// ```
+impl Parse for WrappedMod
+{
+	/// Constructs a WrappedMod from a `syn::ParseStream`.
+	fn parse(input: ParseStream) -> syn::Result<Self>
+	{
+		let item_mod: ItemMod = input.parse()?;
+		// Modules declared with `mod name;` don't have any functions to wrap
+		if let Some(semi) = &item_mod.semi
+		{
+			return Err(syn::Error::new(semi.span, ERROR_STRS[8]));
+		}
+		Ok(WrappedMod::from(item_mod))
+	}
+}
+
+/// Allows `syn::ItemMod`s that have already been parsed to be wrapped.
+impl From<ItemMod> for WrappedMod
+{
+	/// Constructs a WrappedMod from a module. Modules that aren't inline don't have any functions.
+	fn from(item_mod: ItemMod) -> Self
+	{
+		// Turn each function in the module into a WrappedFn
+		let mut functions = Vec::new();
+		if let Some((_, items)) = &item_mod.content
+		{
+			get_item_functions(items, &mut functions);
+		}
+		Self
+		{
+			item_mod,
+			functions,
+			filter: FnFilter::default()
+		}
+	}
+}
+
+/// Allows `WrappedMod`s to be converted to `syn::ItemMod`s for easier use in procedural macros.
+impl From<&WrappedMod> for ItemMod
+{
+	/// Converts a `WrappedMod` into a `syn::ItemMod`.
+	fn from(item_mod: &WrappedMod) -> Self
+	{
+		// Replace each function with its wrapped counterpart, in order
+		let mut new_mod = item_mod.item_mod.clone();
+		if let Some((_, items)) = &mut new_mod.content
+		{
+			replace_item_functions(items, &mut item_mod.functions.iter(), &item_mod.filter);
+		}
+		new_mod
+	}
+}
+
+/// Allows `WrappedMod`s to be converted to `syn::ItemMod`s for easier use in procedural macros.
+impl From<WrappedMod> for ItemMod
+{
+	/// Converts a `WrappedMod` into a `syn::ItemMod`.
+	fn from(item_mod: WrappedMod) -> Self
+	{
+		// Use the `From<&WrappedMod> for ItemMod` implementation
+		Self::from(&item_mod)
+	}
+}
+
+/// Allows `WrappedMod`s to be converted into `proc_macro2::TokenStream`s for easier use in procedural attribute macros.
+impl From<&WrappedMod> for TokenStream
+{
+	/// Converts a `WrappedMod` into a `proc_macro2::TokenStream`.
+	fn from(item_mod: &WrappedMod) -> Self
+	{
+		// Convert the module to a `syn::ItemMod` and then convert that into a `TokenStream`
+		let item_mod = ItemMod::from(item_mod);
+		quote!{ #item_mod }
+	}
+}
+
+/// Allows `WrappedMod`s to be converted into `proc_macro2::TokenStream`s for easier use in procedural attribute macros.
+impl From<WrappedMod> for TokenStream
+{
+	/// Converts a `WrappedMod` into a `proc_macro2::TokenStream`.
+	fn from(item_mod: WrappedMod) -> Self
+	{
+		// Use the `From<&WrappedMod> for TokenStream` implementation
+		Self::from(&item_mod)
+	}
+}
+
+/// Allows `WrappedMod`s to be easily appended to and converted to `proc_macro2::TokenStream`s.
+impl ToTokens for WrappedMod
+{
+	/// Adds a `WrappedMod` to the end of a `proc_macro2::TokenStream`.
+	fn to_tokens(&self, tokens: &mut TokenStream)
+	{
+		// Convert the module into a TokenStream
+		let item_mod: TokenStream = self.into();
+		// Add the module to the end of the TokenStream
+		tokens.extend(item_mod);
+	}
+}
+
+/// Turns each function in a list of items into a `WrappedFn`, going into impl blocks, trait definitions, and nested inline modules.
+///
+/// Inputs:
+///
+/// `items`: The items, such as the contents of a module or a file.
+///
+/// `functions`: Where the functions get added, in the order that they appear in the items.
+pub(crate) fn get_item_functions(items: &[Item], functions: &mut Vec<WrappedFn>)
+{
+	for item in items
+	{
+		match item
+		{
+			Item::Fn(function) => functions.push(WrappedFn::from(function.clone())),
+			Item::Impl(item_impl) => functions.extend(get_impl_methods(item_impl)),
+			Item::Trait(item_trait) => functions.extend(get_trait_methods(item_trait)),
+			Item::Mod(ItemMod{ content: Some((_, nested_items)), .. }) => get_item_functions(nested_items, functions),
+			_ => ()
+		}
+	}
+}
+
+/// Replaces each function in a list of items with its wrapped counterpart, in the same order that `get_item_functions` found them, and removes the filter's skip attribute from all of them.
+///
+/// Inputs:
+///
+/// `items`: The items that get their functions replaced.
+///
+/// `functions`: The wrapped functions, in the order that they appear in the items.
+///
+/// `filter`: The filter whose skip attribute gets removed.
+pub(crate) fn replace_item_functions<'a>(items: &mut [Item], functions: &mut impl Iterator<Item = &'a WrappedFn>, filter: &FnFilter)
+{
+	for item in items
+	{
+		match item
+		{
+			Item::Fn(function) =>
+			{
+				if let Some(wrapped_function) = functions.next()
+				{
+					*function = ItemFn::from(wrapped_function);
+				}
+				// The skip attribute isn't a real attribute, so it can't be left on the function
+				filter.remove_skip_attrs(&mut function.attrs);
+			},
+			Item::Impl(item_impl) => replace_impl_methods(item_impl, functions, filter),
+			Item::Trait(item_trait) => replace_trait_methods(item_trait, functions, filter),
+			Item::Mod(ItemMod{ content: Some((_, nested_items)), .. }) => replace_item_functions(nested_items, functions, filter),
+			_ => ()
+		}
+	}
+}
+
+/// Turns each method with a default implementation in a trait definition into a `WrappedFn` that knows about the generic parameters of the trait.
+///
+/// Inputs:
+///
+/// `item_trait`: The trait definition.
+///
+/// Outputs: The methods with default implementations in the order that they appear in the trait definition.
+fn get_trait_methods(item_trait: &ItemTrait) -> impl Iterator<Item = WrappedFn> + '_
+{
+	item_trait.items.iter().filter_map(|item| match item
+	{
+		TraitItem::Fn(method) if method.default.is_some() =>
+		{
+			let mut method = WrappedFn::from(method.clone());
+			method.set_outer_generics(item_trait.generics.clone());
+			Some(method)
+		},
+		_ => None
+	})
+}
+
+/// Replaces each method with a default implementation in a trait definition with its wrapped counterpart, in order, and removes the filter's skip attribute from all of the trait's methods.
+///
+/// Inputs:
+///
+/// `item_trait`: The trait definition that gets its methods replaced.
+///
+/// `methods`: The wrapped methods, in the order that they appear in the trait definition. Only one is taken for each method with a default implementation.
+///
+/// `filter`: The filter whose skip attribute gets removed.
+fn replace_trait_methods<'a>(item_trait: &mut ItemTrait, methods: &mut impl Iterator<Item = &'a WrappedFn>, filter: &FnFilter)
+{
+	for item in &mut item_trait.items
+	{
+		if let TraitItem::Fn(method) = item
+		{
+			// Methods without a default implementation weren't turned into `WrappedFn`s
+			if method.default.is_some()
+			{
+				if let Some(wrapped_method) = methods.next()
+				{
+					*method = TraitItemFn::from(wrapped_method);
+				}
+			}
+			// The skip attribute isn't a real attribute, so it can't be left on the method
+			filter.remove_skip_attrs(&mut method.attrs);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	#[test]
+	fn wraps_functions_in_nested_items()
+	{
+		let mut item_mod: WrappedMod = syn::parse_quote!
+		{
+			mod handlers
+			{
+				const LIMIT: usize = 8;
+				pub fn handle_get() -> u8 { 1 }
+				fn helper() -> u8 { 2 }
+				impl Handler
+				{
+					pub fn handle_put(&self) -> u8 { 3 }
+				}
+				mod nested
+				{
+					#[no_wrap]
+					pub fn handle_delete() -> u8 { 4 }
+					pub fn handle_post() -> u8 { 5 }
+				}
+			}
+		};
+		item_mod.filter.add_name_pattern("handle_*");
+		item_mod.filter.set_skip_attr("no_wrap");
+		assert_eq!(item_mod.functions.len(), 5);
+		for function in item_mod.get_functions_mut()
+		{
+			function.set_pre_code(quote!{ trace(); });
+		}
+		let expected: ItemMod = syn::parse_quote!
+		{
+			mod handlers
+			{
+				const LIMIT: usize = 8;
+				pub fn handle_get() -> u8 { trace(); 1 }
+				fn helper() -> u8 { 2 }
+				impl Handler
+				{
+					pub fn handle_put(&self) -> u8 { trace(); 3 }
+				}
+				mod nested
+				{
+					pub fn handle_delete() -> u8 { 4 }
+					pub fn handle_post() -> u8 { trace(); 5 }
+				}
+			}
+		};
+		assert_eq!(ItemMod::from(&item_mod), expected);
+	}
+
+	#[test]
+	fn wraps_default_methods_in_traits()
+	{
+		let mut item_mod: WrappedMod = syn::parse_quote!
+		{
+			mod handlers
+			{
+				pub trait Handler<T>
+				{
+					fn handle(&self, request: T) -> u8;
+					fn handle_twice(&self, request: T) -> u8 where T: Clone { self.handle(request.clone()) + self.handle(request) }
+					#[no_wrap]
+					fn handle_none(&self) -> u8 { 0 }
+				}
+				pub fn handle_get() -> u8 { 1 }
+			}
+		};
+		item_mod.filter.set_skip_attr("no_wrap");
+		// Only methods with default implementations have code to wrap
+		assert_eq!(item_mod.functions.len(), 3);
+		assert_eq!(item_mod.functions[0].outer_generics.params.len(), 1);
+		for function in item_mod.get_functions_mut()
+		{
+			function.set_pre_code(quote!{ trace(); });
+		}
+		let expected: ItemMod = syn::parse_quote!
+		{
+			mod handlers
+			{
+				pub trait Handler<T>
+				{
+					fn handle(&self, request: T) -> u8;
+					fn handle_twice(&self, request: T) -> u8 where T: Clone { trace(); self.handle(request.clone()) + self.handle(request) }
+					fn handle_none(&self) -> u8 { 0 }
+				}
+				pub fn handle_get() -> u8 { trace(); 1 }
+			}
+		};
+		assert_eq!(ItemMod::from(&item_mod), expected);
+	}
+
+	#[test]
+	fn rejects_modules_that_are_not_inline()
+	{
+		let error = syn::parse2::<WrappedMod>(quote!{ mod handlers; }).unwrap_err();
+		assert_eq!(error.to_string(), ERROR_STRS[8]);
+	}
+}
@@ -0,0 +1,24 @@
+use function_wrapper::WrappedFile;
+use quote::quote;
+
+/// Weaves print statements into the public functions of a source file that doesn't have any attributes and writes it to `OUT_DIR`.
+fn main()
+{
+	println!("cargo:rerun-if-changed=generated/counters.rs");
+	// Parse the file as a WrappedFile object from the function-wrapper crate.
+	let mut file = WrappedFile::read("generated/counters.rs").unwrap();
+	// Only wrap public functions.
+	file.filter.set_pub_only(true);
+	for function in file.get_functions_mut()
+	{
+		// Put print statements with the name of the function in the code that gets run before and after each function.
+		// The name goes into the format strings since printing a literal with `{}` trips a lint.
+		let name = function.function.sig.ident.to_string();
+		let start = format!("Hi at the start of woven {} :)", name);
+		let end = format!("Hi at the end of woven {} :)", name);
+		function.set_pre_code(quote!{ println!(#start); });
+		function.set_post_code(quote!{ println!(#end); });
+	}
+	// Write the woven file to OUT_DIR so it can be included.
+	file.write_to_out_dir("counters.rs").unwrap();
+}
@@ -0,0 +1,25 @@
+// Stands in for generated code that can't have attributes put on it.
+
+pub struct Counter
+{
+	pub count: u32
+}
+
+impl Counter
+{
+	pub fn increment(&mut self) -> u32
+	{
+		self.count += 1;
+		self.count
+	}
+}
+
+pub fn new_counter() -> Counter
+{
+	Counter{count: 0}
+}
+
+fn unused_private() -> u32
+{
+	0
+}
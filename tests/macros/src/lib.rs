@@ -1,4 +1,4 @@
-use function_wrapper::WrappedFn;
+use function_wrapper::{WrappedFn, WrappedImpl, WrappedMod, WrappedClosure, FnFilter, ExpansionStrategy, PanicConversion};
 extern crate proc_macro;
 extern crate proc_macro2;
 use syn::{parse_macro_input, FnArg};
@@ -79,14 +79,129 @@ pub fn test_attr(parms: proc_macro::TokenStream, item: proc_macro::TokenStream)
 		},
 		"pre" =>
 		{
+			// Put a print statement in the code that gets run before the function, making sure that it parses first.
+			if let Err(error) = function.try_set_pre_code(quote!{ println!("Hi at the start :)"); })
+			{
+				return error.to_compile_error().into();
+			}
+		},
+		"post" =>
+		{
+			// Put a print statement in the code that gets run after the function.
+			function.set_post_code(quote!{ println!("Hi at the end :)"); });
+		},
+		"async" =>
+		{
+			// Put an awaited print statement in the code that gets run before the function.
+			if let Err(error) = function.set_async_pre_code(quote!{ std::future::ready(()).await; println!("Hi at the async start :)"); })
+			{
+				return error.to_compile_error().into();
+			}
+			// Put an awaited print statement in the code that gets run after the function.
+			if let Err(error) = function.set_async_post_code(quote!{ std::future::ready(()).await; println!("Hi at the async end :)"); })
+			{
+				return error.to_compile_error().into();
+			}
+		},
+		"cancel" =>
+		{
+			// Put a print statement in the code that gets run if the function's future is dropped early.
+			if let Err(error) = function.set_cancel_code(quote!{ println!("Hi at the cancellation :("); })
+			{
+				return error.to_compile_error().into();
+			}
+			// Put a print statement in the code that gets run after the function.
+			function.set_post_code(quote!{ println!("Hi at the end :)"); });
+		},
+		"block" =>
+		{
+			// Put the original code in a labeled block instead of a closure.
+			function.set_expansion_strategy(ExpansionStrategy::Block);
+			// Put a print statement in the code that gets run after the function.
+			function.set_post_code(quote!{ println!("Hi at the end :)"); });
+		},
+		"caller" =>
+		{
+			// Put a print statement that shows where the function was called from in the code that gets run after the function.
+			let caller = &function.caller_ident;
+			function.set_post_code(quote!{ println!("Hi at the end, called from line {} :)", #caller.line()); });
+		},
+		"hygiene" =>
+		{
+			// Keep the result variable from colliding with anything in the function and print it in the code that gets run after the function.
+			function.set_hygienic_idents(true);
+			let result = function.get_result_ident();
+			function.set_post_code(quote!{ println!("Hi at the end, returning {} :)", #result); });
+		},
+		"const" =>
+		{
+			// Put code that can run in const contexts before and after the function.
+			function.set_pre_code(quote!{ let calls: u32 = 1; });
+			function.set_post_code(quote!{ assert!(calls == 1, "Hi at the end :)"); });
+		},
+		"inner" =>
+		{
+			// Move the original code into a nested function instead of a closure.
+			function.set_expansion_strategy(ExpansionStrategy::InnerFn);
 			// Put a print statement in the code that gets run before the function.
 			function.set_pre_code(quote!{ println!("Hi at the start :)"); });
+			// Put a print statement in the code that gets run after the function.
+			function.set_post_code(quote!{ println!("Hi at the end :)"); });
 		},
-		"post" =>
+		"finally" =>
+		{
+			// Put a print statement in the code that gets run however the function exits.
+			let panicking = &function.panicking_ident;
+			function.set_finally_code(quote!{ println!("Hi at the finally (panicking: {}) :)", #panicking); });
+		},
+		"panic" =>
 		{
+			// Put a print statement of the panic payload in the code that gets run when the function panics.
+			let payload = &function.payload_ident;
+			let fn_name = &function.fn_name_ident;
+			function.set_panic_code(quote!{ println!("Hi at the panic in {} ({:?}) :(", #fn_name, #payload.downcast_ref::<&str>()); });
 			// Put a print statement in the code that gets run after the function.
 			function.set_post_code(quote!{ println!("Hi at the end :)"); });
 		},
+		"diverge" =>
+		{
+			// Put print statements in the code that gets run before the function, when it panics, and however it exits, since functions that never return can't have post code.
+			function.set_pre_code(quote!{ println!("Hi at the start :)"); });
+			let payload = &function.payload_ident;
+			function.set_panic_code(quote!{ println!("Hi at the panic ({:?}) :(", #payload.downcast_ref::<&str>()); });
+			function.set_finally_code(quote!{ println!("Hi at the finally :)"); });
+		},
+		"catch" =>
+		{
+			// Return panic payloads as errors instead of unwinding.
+			function.set_panic_conversion(PanicConversion::Payload).unwrap();
+		},
+		"nest" =>
+		{
+			// Return panics as the function's own error type instead of unwinding.
+			let payload = &function.payload_ident;
+			let fn_name = &function.fn_name_ident;
+			let conversion = PanicConversion::Nest{ convert: quote!{ TestError(format!("{} panicked: {:?}", #fn_name, #payload.downcast_ref::<&str>())) } };
+			if let Err(error) = function.set_panic_conversion(conversion)
+			{
+				return error.to_compile_error().into();
+			}
+		},
+		"sentinel" =>
+		{
+			// Record the panic and return -1 instead of unwinding across the FFI boundary.
+			let fn_name = &function.fn_name_ident;
+			function.set_panic_code(quote!{ println!("Hi at the panic in {} :(", #fn_name); });
+			if let Err(error) = function.set_panic_sentinel(quote!{ -1 })
+			{
+				return error.to_compile_error().into();
+			}
+		},
+		"unsafe_hooks" =>
+		{
+			// Dereference the function's pointer parameter in the code that gets run before the function without an unsafe block, which shouldn't compile.
+			function.set_pre_code(quote!{ let first = *p; println!("Hi at the start, reading {} and {} :)", first, *p); });
+		},
 		"" | "none" => (),
 		_ => panic!("Invalid attribute parameter.")
 	}
@@ -96,6 +211,70 @@ pub fn test_attr(parms: proc_macro::TokenStream, item: proc_macro::TokenStream)
 	ts.into()
 }
 
+/// Adds print statements before and after every public method in an impl block that doesn't have `#[skip]`.
+#[proc_macro_attribute]
+pub fn test_impl_attr(_: proc_macro::TokenStream, item: proc_macro::TokenStream) -> proc_macro::TokenStream
+{
+	// Parse input as a WrappedImpl object from the function-wrapper crate.
+	let mut item_impl = parse_macro_input!(item as WrappedImpl);
+	// Only wrap public methods, and let methods opt out with `#[skip]`.
+	item_impl.filter.set_pub_only(true);
+	item_impl.filter.set_skip_attr("skip");
+	// Get the type that the impl block is for so it can be printed.
+	let self_ty = &item_impl.item_impl.self_ty;
+	let self_ty = quote!{ #self_ty }.to_string();
+	for method in item_impl.get_methods_mut()
+	{
+		// Put print statements with the name of the method in the code that gets run before and after each method.
+		let name = format!("{}::{}", self_ty, method.function.sig.ident);
+		method.set_pre_code(quote!{ println!("Hi at the start of {} :)", #name); });
+		method.set_post_code(quote!{ println!("Hi at the end of {} :)", #name); });
+	}
+	// Convert the impl block into a TokenStream and return it.
+	let ts = proc_macro2::TokenStream::from(item_impl);
+	println!("{}", ts.clone());
+	ts.into()
+}
+
+/// Adds a print statement before every function in a module that matches the selectors given to the attribute.
+#[proc_macro_attribute]
+pub fn test_mod_attr(parms: proc_macro::TokenStream, item: proc_macro::TokenStream) -> proc_macro::TokenStream
+{
+	// Parse the attribute's parameters as a FnFilter and the input as a WrappedMod object from the function-wrapper crate.
+	let filter = parse_macro_input!(parms as FnFilter);
+	let mut item_mod = parse_macro_input!(item as WrappedMod);
+	// Only wrap functions that pass the filter.
+	item_mod.set_filter(filter);
+	for function in item_mod.get_functions_mut()
+	{
+		// Put a print statement with the name of the function in the code that gets run before each function.
+		let name = function.function.sig.ident.to_string();
+		function.set_pre_code(quote!{ println!("Hi at the start of {} :)", #name); });
+	}
+	// Convert the module into a TokenStream and return it.
+	let ts = proc_macro2::TokenStream::from(item_mod);
+	println!("{}", ts.clone());
+	ts.into()
+}
+
+/// Adds print statements before and after a closure runs, printing what it returned.
+#[proc_macro]
+pub fn test_closure(item: proc_macro::TokenStream) -> proc_macro::TokenStream
+{
+	// Parse input as a WrappedClosure object from the function-wrapper crate.
+	let mut closure = parse_macro_input!(item as WrappedClosure);
+	// Put a print statement in the code that gets run before the closure.
+	closure.set_pre_code(quote!{ println!("Hi at the start of the closure :)"); });
+	// Put a print statement of the closure's return value in the code that gets run after the closure, using hygienic identifiers so that they can't collide with anything in it.
+	closure.set_hygienic_idents(true);
+	let result_ident = closure.get_result_ident();
+	closure.set_post_code(quote!{ println!("Hi at the end of the closure, returning {:?} :)", #result_ident); });
+	// Convert the closure into a TokenStream and return it.
+	let ts = proc_macro2::TokenStream::from(closure);
+	println!("{}", ts.clone());
+	ts.into()
+}
+
 #[cfg(test)]
 mod tests
 {
@@ -1,4 +1,6 @@
 use macros::*;
+use std::future::Future;
+use std::task::{Context, Poll, Waker};
 
 fn main()
 {
@@ -11,6 +13,24 @@ fn test_all()
 	pre_test();
 	post_test();
 	none_test();
+	question_mark_test();
+	block_test();
+	inner_test();
+	finally_test();
+	panic_test();
+	catch_test();
+	sentinel_test();
+	const_test();
+	caller_test();
+	diverging_test();
+	unsafe_test();
+	collision_test();
+	method_test();
+	impl_test();
+	mod_test();
+	file_test();
+	closure_test();
+	async_test();
 }
 
 fn access_parameters_test()
@@ -50,6 +70,468 @@ fn none_test()
 	none_fn("a", 1, TestStruct{x: 10, y: 12, z: 14}, ("ahoy", 200, true));
 }
 
+fn async_test()
+{
+	block_on(async_fn("a", 1));
+	block_on(async_hooks_fn("a", 1));
+	cancel_test();
+}
+
+fn closure_test()
+{
+	let greeting = String::from("Closure");
+	let greet = test_closure!(move |name: &str| -> String { format!("{} {}", greeting, name) });
+	println!("{}", greet("test"));
+	let clamp = test_closure!(|x: i32| { if x < 0 { return 0; } x });
+	println!("{}", [-5, 5].into_iter().map(clamp).sum::<i32>());
+	let offset = 10;
+	let add_async = test_closure!(async move |x: i32| { std::future::ready(()).await; x + offset });
+	println!("{}", block_on(add_async(1)));
+}
+
+fn file_test()
+{
+	let mut counter = counters::new_counter();
+	println!("{}", counter.increment());
+}
+
+fn mod_test()
+{
+	println!("{}", handlers::handle_get());
+	println!("{}", handlers::Handler.handle_put());
+	println!("{}", handlers::nested::handle_post());
+	println!("{}", handlers::nested::handle_patch());
+}
+
+fn impl_test()
+{
+	let mut wrapper = GenericWrapper{value: 5u8};
+	wrapper.set(7);
+	println!("{}", wrapper.get());
+	println!("{}", wrapper.doubled());
+	println!("{:?}", GenericWrapper::<u8>::new_default().value);
+}
+
+fn method_test()
+{
+	let test_struct = TestStruct{x: 1, y: 2, z: 3};
+	println!("{}", test_struct.sum());
+	println!("{}", test_struct.doubled_sum());
+}
+
+fn collision_test()
+{
+	println!("{}", collision_fn(1, 2));
+	println!("{}", hygiene_fn(1, 2));
+}
+
+fn unsafe_test()
+{
+	let x = 21;
+	println!("{}", unsafe { unsafe_fn(&x) });
+}
+
+fn diverging_test()
+{
+	println!("{:?}", std::panic::catch_unwind(|| diverging_fn()).is_err());
+}
+
+fn caller_test()
+{
+	println!("{}", caller_fn());
+	println!("{:?}", caller_option_fn(Some(3)));
+	println!("{:?}", caller_option_fn(None));
+}
+
+fn const_test()
+{
+	const DOUBLED: u32 = const_fn(21);
+	println!("{}", DOUBLED);
+	println!("{}", const_fn(4));
+}
+
+fn sentinel_test()
+{
+	println!("{}", sentinel_fn(false));
+	println!("{}", sentinel_fn(true));
+}
+
+fn catch_test()
+{
+	println!("{:?}", catch_fn(false).map_err(|payload| payload.downcast_ref::<&str>().copied()));
+	println!("{:?}", catch_fn(true).map_err(|payload| payload.downcast_ref::<&str>().copied()));
+	println!("{:?}", nest_fn(false));
+	// Print the message that the panic got converted into.
+	if let Err(error) = nest_fn(true)
+	{
+		println!("{}", error);
+	}
+}
+
+fn panic_test()
+{
+	println!("{:?}", panic_fn(false));
+	println!("{:?}", std::panic::catch_unwind(|| panic_fn(true)).is_err());
+	println!("{:?}", block_on(async_panic_fn(false)));
+	println!("{:?}", std::panic::catch_unwind(|| block_on(async_panic_fn(true))).is_err());
+}
+
+fn finally_test()
+{
+	println!("{:?}", finally_fn(false));
+	println!("{:?}", std::panic::catch_unwind(|| finally_fn(true)).is_err());
+}
+
+fn inner_test()
+{
+	inner_fn("a", 1, TestStruct{x: 10, y: 12, z: 14}, ("ahoy", 200, true));
+	println!("{}", inner_generic_fn::<u64>(3));
+	let mut v = vec![1, 2, 3];
+	*inner_first_fn(&mut v) += 10;
+	println!("{:?}", v);
+}
+
+fn block_test()
+{
+	let mut v = vec![1, 2, 3];
+	*block_first_fn(&mut v) += 10;
+	println!("{:?}", v);
+	println!("{:?}", block_question_mark_fn("12"));
+	println!("{:?}", block_question_mark_fn("twelve"));
+	println!("{:?}", block_question_mark_fn("-1"));
+	let mut v = vec![1, 2, 3];
+	println!("{:?}", block_option_fn(&mut v, 2));
+	println!("{:?}", block_option_fn(&mut v, 5));
+}
+
+fn question_mark_test()
+{
+	println!("{:?}", question_mark_fn("12"));
+	println!("{:?}", question_mark_fn("twelve"));
+}
+
+fn cancel_test()
+{
+	// Run one future to completion and drop the others after they get polled once.
+	block_on(cancel_fn(1));
+	poll_once(cancel_fn(2));
+	poll_once(cancel_future_fn(3));
+	// The post code of a function that returns a future should only run once the future completes.
+	block_on(cancel_future_fn(4));
+	// Futures that get dropped because they panicked weren't cancelled, so the cancellation code shouldn't run for them.
+	println!("{:?}", std::panic::catch_unwind(|| block_on(cancel_panic_fn(5))).is_err());
+	println!("{:?}", std::panic::catch_unwind(|| block_on(cancel_future_panic_fn(6))).is_err());
+}
+
+/// Polls a future once and then drops it.
+fn poll_once<F: Future>(future: F)
+{
+	let mut future = std::pin::pin!(future);
+	let mut context = Context::from_waker(Waker::noop());
+	let _ = future.as_mut().poll(&mut context);
+}
+
+/// Future that is pending the first time it gets polled.
+struct YieldOnce(bool);
+
+impl Future for YieldOnce
+{
+	type Output = ();
+
+	fn poll(mut self: std::pin::Pin<&mut Self>, _: &mut Context<'_>) -> Poll<()>
+	{
+		if self.0
+		{
+			Poll::Ready(())
+		}
+		else
+		{
+			self.0 = true;
+			Poll::Pending
+		}
+	}
+}
+
+/// Runs a future to completion on the current thread.
+fn block_on<F: Future>(future: F) -> F::Output
+{
+	let mut future = std::pin::pin!(future);
+	let mut context = Context::from_waker(Waker::noop());
+	loop
+	{
+		if let Poll::Ready(output) = future.as_mut().poll(&mut context)
+		{
+			return output;
+		}
+	}
+}
+
+async fn ready_value(b: i32) -> i32
+{
+	b * 2
+}
+
+#[test_attr(both)]
+async fn async_fn(a: &str, b: i32) -> i32
+{
+	println!("Async");
+	println!("-------------------- Arguments --------------------");
+	println!("a: {}, b: {}", a, b);
+	println!("---------------------------------------------------");
+	ready_value(b).await
+}
+
+#[test_attr(async)]
+async fn async_hooks_fn(a: &str, b: i32) -> i32
+{
+	println!("Async Hooks");
+	println!("-------------------- Arguments --------------------");
+	println!("a: {}, b: {}", a, b);
+	println!("---------------------------------------------------");
+	ready_value(b).await
+}
+
+#[test_attr(cancel)]
+async fn cancel_fn(b: i32) -> i32
+{
+	println!("Cancel {}", b);
+	YieldOnce(false).await;
+	b
+}
+
+#[test_attr(cancel)]
+fn cancel_future_fn(b: i32) -> impl Future<Output = i32>
+{
+	println!("Cancel Future {}", b);
+	async move
+	{
+		YieldOnce(false).await;
+		b
+	}
+}
+
+#[test_attr(cancel)]
+async fn cancel_panic_fn(b: i32) -> i32
+{
+	println!("Cancel Panic {}", b);
+	YieldOnce(false).await;
+	panic!("Cancel Panic {}", b)
+}
+
+#[test_attr(cancel)]
+fn cancel_future_panic_fn(b: i32) -> impl Future<Output = i32>
+{
+	println!("Cancel Future Panic {}", b);
+	async move
+	{
+		YieldOnce(false).await;
+		panic!("Cancel Future Panic {}", b)
+	}
+}
+
+#[derive(Debug)]
+struct TestError(String);
+
+impl std::fmt::Display for TestError
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+	{
+		write!(f, "TestError: {}", self.0)
+	}
+}
+
+impl From<std::num::ParseIntError> for TestError
+{
+	fn from(error: std::num::ParseIntError) -> Self
+	{
+		TestError(error.to_string())
+	}
+}
+
+#[test_attr(post)]
+fn question_mark_fn(a: &str) -> Result<i32, TestError>
+{
+	println!("Question Mark");
+	let b: i32 = a.parse()?;
+	Ok(b * 2)
+}
+
+#[test_attr(both)]
+unsafe fn unsafe_fn(p: *const i32) -> i32
+{
+	println!("Unsafe");
+	*p * 2
+}
+
+#[test_attr(post)]
+fn collision_fn(result: i32, wrapper: i32) -> i32
+{
+	println!("Collision");
+	result + wrapper
+}
+
+#[test_attr(hygiene)]
+fn hygiene_fn(result: i32, wrapper: i32) -> i32
+{
+	println!("Hygiene");
+	result * wrapper
+}
+
+#[test_attr(diverge)]
+fn diverging_fn() -> !
+{
+	println!("Diverging");
+	panic!("never returns")
+}
+
+#[test_attr(caller)]
+#[track_caller]
+fn caller_fn() -> u32
+{
+	println!("Caller");
+	std::panic::Location::caller().line()
+}
+
+#[test_attr(caller)]
+#[track_caller]
+fn caller_option_fn(a: Option<u32>) -> Option<u32>
+{
+	println!("Caller Option");
+	let b = a?;
+	Some(b + std::panic::Location::caller().line())
+}
+
+#[test_attr(const)]
+const fn const_fn(a: u32) -> u32
+{
+	if a > 100
+	{
+		return 0;
+	}
+	a * 2
+}
+
+#[test_attr(block)]
+fn block_first_fn(v: &mut [i32]) -> &mut i32
+{
+	println!("Block First");
+	&mut v[0]
+}
+
+#[test_attr(block)]
+fn block_question_mark_fn(a: &str) -> Result<i32, TestError>
+{
+	println!("Block Question Mark");
+	let b: i32 = a.parse()?;
+	if b < 0
+	{
+		return Err(TestError(String::from("negative")));
+	}
+	Ok(b * 2)
+}
+
+#[test_attr(block)]
+fn block_option_fn(v: &mut [i32], target: i32) -> Option<&mut i32>
+{
+	println!("Block Option");
+	// `?` on an `Option` has to break out with the function's return type, not the type that it was used on
+	let i = v.iter().position(|x| *x == target)?;
+	Some(&mut v[i])
+}
+
+#[test_attr(inner)]
+fn inner_fn(a: &str, b: i32, TestStruct{x: c, y: d, z: e}: TestStruct, (foo, bar, baz): (&str, u8, bool)) -> f64
+{
+	println!("Inner");
+	println!("-------------------- Arguments --------------------");
+	println!("a: {}, b: {}, c: {}, d: {}, e: {}, foo: {}, bar: {}, baz: {}", a, b, c, d, e, foo, bar, baz);
+	println!("---------------------------------------------------");
+	1.1
+}
+
+#[test_attr(inner)]
+fn inner_generic_fn<T: Default + std::fmt::Debug>(mut count: usize) -> String
+{
+	count += 1;
+	format!("{:?} x {}", T::default(), count)
+}
+
+#[test_attr(inner)]
+fn inner_first_fn(v: &mut [i32]) -> &mut i32
+{
+	&mut v[0]
+}
+
+#[test_attr(finally)]
+fn finally_fn(panic: bool) -> i32
+{
+	println!("Finally");
+	if panic
+	{
+		panic!("Finally panic");
+	}
+	1
+}
+
+#[test_attr(panic)]
+fn panic_fn(panic: bool) -> Result<i32, TestError>
+{
+	println!("Panic");
+	if panic
+	{
+		panic!("Panic panic");
+	}
+	let b: i32 = "2".parse()?;
+	Ok(b)
+}
+
+#[test_attr(panic)]
+async fn async_panic_fn(panic: bool) -> Result<i32, TestError>
+{
+	println!("Async Panic");
+	YieldOnce(false).await;
+	if panic
+	{
+		panic!("Async panic panic");
+	}
+	let b: i32 = "2".parse()?;
+	Ok(b)
+}
+
+#[test_attr(catch)]
+fn catch_fn(panic: bool) -> i32
+{
+	println!("Catch");
+	if panic
+	{
+		panic!("Catch panic");
+	}
+	1
+}
+
+#[test_attr(nest)]
+fn nest_fn(panic: bool) -> Result<i32, TestError>
+{
+	println!("Nest");
+	if panic
+	{
+		panic!("Nest panic");
+	}
+	let b: i32 = "2".parse()?;
+	Ok(b)
+}
+
+#[test_attr(sentinel)]
+extern "C" fn sentinel_fn(panic: bool) -> i32
+{
+	println!("Sentinel");
+	if panic
+	{
+		panic!("Sentinel panic");
+	}
+	1
+}
+
 #[test_attr(both)]
 fn both_fn(a: &str, b: i32, TestStruct{x: c, y: d, z: e}: TestStruct, (foo, bar, baz): (&str, u8, bool)) -> f64
 {
@@ -108,6 +590,111 @@ struct TestStruct
 	pub z: i8
 }
 
+trait SumTrait
+{
+	#[test_attr(pre)]
+	fn sum(&self) -> i32;
+
+	#[test_attr(post)]
+	fn doubled_sum(&self) -> i32
+	{
+		println!("Doubled Sum");
+		self.sum() * 2
+	}
+}
+
+impl SumTrait for TestStruct
+{
+	#[test_attr(both)]
+	fn sum(&self) -> i32
+	{
+		println!("Sum");
+		(self.x + self.y + self.z) as i32
+	}
+}
+
+struct GenericWrapper<T>
+{
+	value: T
+}
+
+#[test_impl_attr]
+impl<T: Copy + Default + std::ops::Add<Output = T>> GenericWrapper<T>
+{
+	pub fn get(&self) -> T
+	{
+		self.value
+	}
+
+	#[skip]
+	pub fn set(&mut self, value: T)
+	{
+		self.value = value;
+	}
+
+	pub fn doubled(&self) -> T
+	{
+		self.value + self.value
+	}
+
+	pub fn new_default() -> Self
+	{
+		let value: T = T::default();
+		Self{value}
+	}
+
+	#[allow(dead_code)]
+	fn private(&self) -> T
+	{
+		self.value
+	}
+}
+
+#[test_mod_attr(pub, name = "handle_*", returns = u8, skip = no_wrap)]
+mod handlers
+{
+	pub struct Handler;
+
+	pub fn handle_get() -> u8
+	{
+		helper() + 1
+	}
+
+	fn helper() -> u8
+	{
+		println!("Helper");
+		1
+	}
+
+	impl Handler
+	{
+		pub fn handle_put(&self) -> u8
+		{
+			3
+		}
+	}
+
+	pub mod nested
+	{
+		pub fn handle_post() -> u8
+		{
+			5
+		}
+
+		#[no_wrap]
+		pub fn handle_patch() -> u8
+		{
+			6
+		}
+	}
+}
+
+#[allow(dead_code)]
+mod counters
+{
+	include!(concat!(env!("OUT_DIR"), "/counters.rs"));
+}
+
 trait TestTrait
 {
 	fn access_parameters_mtd(&self, i: i32) -> f64;
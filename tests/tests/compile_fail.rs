@@ -0,0 +1,7 @@
+/// Makes sure that code which shouldn't compile after being wrapped doesn't.
+#[test]
+fn compile_fail_test()
+{
+	let cases = trybuild::TestCases::new();
+	cases.compile_fail("tests/ui/*.rs");
+}
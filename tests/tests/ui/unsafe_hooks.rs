@@ -0,0 +1,14 @@
+use macros::*;
+
+// Code added to an `unsafe fn` doesn't get its unsafe context, so it can't dereference raw pointers without an unsafe block.
+#[test_attr(unsafe_hooks)]
+unsafe fn unsafe_hooks_fn(p: *const i32) -> i32
+{
+	*p * 2
+}
+
+fn main()
+{
+	let x = 21;
+	println!("{}", unsafe { unsafe_hooks_fn(&x) });
+}